@@ -2,6 +2,7 @@ extern crate tarpc;
 extern crate chord;
 
 use std::net::SocketAddr;
+use std::time::Duration;
 use tarpc::future::server;
 use tarpc::tokio_core::reactor;
 use chord::*;
@@ -15,6 +16,7 @@ fn main() {
     let node = Node::new(node_id);
     let query_server = QueryEngine::new(node);
     let chord_server = ChordServer::new(query_server);
+    reactor.handle().spawn(chord_server.sweeper(Duration::from_secs(60)));
 
     let (_, server) = chord_server
         .listen(addr, &reactor.handle(), server::Options::default())
@@ -0,0 +1,22 @@
+extern crate tarpc;
+extern crate chord;
+
+use std::io;
+use std::net::SocketAddr;
+use tarpc::future::client;
+use tarpc::future::client::ClientExt;
+use tarpc::futures::Future;
+use tarpc::tokio_core::reactor;
+use chord::*;
+
+fn main() {
+    let addr: SocketAddr = "0.0.0.0:4646".parse().unwrap();
+    let mut reactor = reactor::Core::new().unwrap();
+
+    let client = FutureClient::connect(addr, client::Options::default())
+        .wait()
+        .unwrap();
+    let export = export_json(client, io::stdout(), JsonFormat::Ndjson, 100);
+    let (_, count) = reactor.run(export).unwrap();
+    eprintln!("Exported {} definitions.", count);
+}
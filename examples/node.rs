@@ -1,46 +1,57 @@
 extern crate tarpc;
 extern crate chord;
 
+use std::env;
 use std::net::SocketAddr;
+use std::time::Duration;
 use tarpc::future::{client, server};
 use tarpc::future::client::ClientExt;
 use tarpc::futures::Future;
 use tarpc::tokio_core::reactor;
 use chord::*;
 
+/// Usage: node [<bind-addr> [<advertised-addr>]]. Binds an ephemeral port by default.
 fn main() {
-    let addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
-    let node_id = Id::from(addr);
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let bind: SocketAddr = args.get(0)
+        .map_or("0.0.0.0:0", |arg| arg.as_str())
+        .parse()
+        .unwrap();
+    let advertise = args.get(1).map(|arg| arg.parse().unwrap());
+    let addrs = NodeAddrs::new(bind, advertise);
 
     let base_node_addr: SocketAddr = "0.0.0.0:4646".parse().unwrap();
     let base_node_id = Id::from(base_node_addr);
 
     let mut reactor = reactor::Core::new().unwrap();
 
-    let node = Node::new(node_id);
+    let node = Node::new(addrs.id());
     let query_server = QueryEngine::new(node);
     let chord_server = ChordServer::new(query_server);
+    reactor.handle().spawn(chord_server.sweeper(Duration::from_secs(60)));
 
     let (server_handle, server) = chord_server
-        .listen(addr, &reactor.handle(), server::Options::default())
+        .clone()
+        .listen(addrs.bind, &reactor.handle(), server::Options::default())
         .unwrap();
-    println!("Node listening on {:?}", server_handle.addr());
+    let bound = addrs.bound_to(server_handle.addr());
+    if bound != addrs {
+        chord_server.rename(bound.id()).wait().unwrap();
+    }
+    println!("Node listening on {:?}, advertised as {:?}",
+             bound.bind,
+             bound.advertise);
 
     let node_client = FutureClient::connect(server_handle.addr(), client::Options::default());
 
     let joiner = node_client
         .map_err(|_| ())
         .and_then(move |c| {
-            let rename = c.rename(Id::from(server_handle.addr()))
-                .map_err(|e| {
-                             println!("not renamed {:?}", e);
-                         });
-            let join = c.join(base_node_id)
-                .map_err(|e| {
-                             println!("not joined {:?}", e);
-                         });
-            rename.and_then(join)
-        })
+                      c.join(base_node_id)
+                          .map_err(|e| {
+                                       println!("not joined {:?}", e);
+                                   })
+                  })
         .map(|_| {
                  println!("joined!");
              });
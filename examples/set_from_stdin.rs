@@ -4,114 +4,107 @@
 #![feature(slice_patterns)]
 #![feature(box_syntax)]
 
-extern crate csv;
-extern crate sha1;
-extern crate rand;
 extern crate tarpc;
 extern crate chord;
 extern crate futures;
 extern crate tokio_core;
-extern crate tokio_timer;
 
-use std::time::Duration;
-use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::fs::{self, File};
 use std::net::SocketAddr;
 use tarpc::future::client;
 use tarpc::future::client::ClientExt;
-use futures::{stream, Future, Stream};
-use tokio_core::reactor::{Core, Handle};
-use tokio_timer::*;
-use rand::{Rng, StdRng};
+use futures::Future;
+use tokio_core::reactor::Core;
+use std::io;
 use std::io::prelude::*;
 use std::io::stdout;
 use chord::*;
 
-fn new_client(id: Id, handle: Handle) -> Connect<FutureClient> {
-    let client_options = client::Options::default(); //.handle(handle);
+fn new_client(id: Id) -> Connect<FutureClient> {
+    let client_options = client::Options::default();
     FutureClient::connect(id.addr, client_options)
 }
 
-//pub type TimeoutResult<I, E> = Result<I, TimeoutErr<E>>;
-
-#[derive(Debug)]
-pub enum TimeoutErr<E> {
-    FutureErr(E),
-    TimedOut,
-}
-
-impl<E, F> From<TimeoutError<F>> for TimeoutErr<E> {
-    fn from(_: TimeoutError<F>) -> TimeoutErr<E> {
-        TimeoutErr::TimedOut
-    }
-}
-
 fn main() {
     let number_of_nodes = 8;
 
-    let timer = Timer::default();
+    let mut reactor = Core::new().unwrap();
 
-    let mut rng = StdRng::new().unwrap();
+    let addr: SocketAddr = format!("0.0.0.0:{:?}", 4646).parse().unwrap();
+    let node_id = Id::from(addr);
+    print!("Connecting to {:?}...", node_id);
+    stdout().flush().unwrap();
+    let client = new_client(node_id).wait().unwrap();
+    println!("connected.");
 
-    let mut reactor = Core::new().unwrap();
-    let handle = reactor.handle();
+    let resume_after = read_checkpoint();
+    if resume_after > 0 {
+        println!("Resuming after line {}.", resume_after);
+    }
+    let rows = match CsvRows::new(io::stdin(), Normalization::default()) {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Could not read CSV header: {}", e);
+            return;
+        }
+    };
+    let options = LoadOptions {
+        batch_size: 100,
+        concurrency: number_of_nodes * 4,
+        retries: 5,
+        backoff: Duration::from_millis(200),
+        resume_after: resume_after,
+    };
 
-    let mut node_clients = HashMap::new();
-    for i in 1..number_of_nodes {
-        //213.138.101.13
-        let addr: SocketAddr = format!("0.0.0.0:{:?}", 4646).parse().unwrap();
-        let node_id = Id::from(addr);
-        print!("Connecting to {:?}...", node_id);
-        stdout().flush().unwrap();
+    let started = Instant::now();
+    let loader = load_rows(rows, client, &options, write_checkpoint);
+    let report = reactor.run(loader).unwrap();
+    let elapsed = started.elapsed();
+    if report.unstored.is_empty() {
+        let _ = fs::remove_file(CHECKPOINT_FILE);
+    }
 
-        let client_future = new_client(node_id, handle.clone());
-        let client = client_future.wait().unwrap();
-        node_clients.insert(node_id, client);
-        println!("connected.");
+    for rejection in &report.rejected {
+        println!("Rejected line {}: {}", rejection.line, rejection.reason);
+    }
+    for rejection in &report.unstored {
+        println!("Could not store line {}: {}", rejection.line, rejection.reason);
+    }
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    println!("Imported {} definitions, rejected {}, could not store {}, {} failed attempts.",
+             report.imported,
+             report.rejected.len(),
+             report.unstored.len(),
+             report.failed_attempts);
+    if !report.unstored.is_empty() {
+        println!("Run again to resume from the checkpoint.");
     }
+    println!("Took {:.1}s, {:.0} definitions/s.",
+             seconds,
+             report.imported as f64 / seconds.max(0.001));
+}
 
-    let definitions = definitions_from_stdin();
-    println!("Loaded defintions.");
+/// Last line known to be stored, written after every batch so an interrupted load can
+/// pick up where it left off.
+const CHECKPOINT_FILE: &'static str = "set_from_stdin.checkpoint";
 
-    let definitions = definitions
-        .into_iter()
-        .map(|(k, d)| Ok((k, d)))
-        .collect::<Vec<Result<_, ()>>>();
-    let mut i = 0;
-    let server = stream::iter(definitions)
-        .map(move |(key, definition)| {
-            // Choose a random client connection.
-            let node_clients_vec = node_clients.iter().collect::<Vec<_>>();
-            let (node_id, node_client) = rng.choose(&node_clients_vec).unwrap().clone();
-            let node_id = *node_id;
-            let node_client = node_client.clone();
-            // Perform SET operation with a timeout.
-            timer
-                .timeout(node_client
-                             .set(key, definition.clone())
-                             .map_err(|e| TimeoutErr::FutureErr(e)),
-                         Duration::from_secs(20))
-                .map(move |r| (node_id, r))
-                .map_err(move |e| {
-                             println!("err3 {:?} {:?}", node_id.addr, e);
-                         })
-        })
-        .buffer_unordered(number_of_nodes * 4)
-        .map(move |(id, r)| {
-                 i += 1;
-                 if i % 1 == 0 {
-                     println!("Set {:?}th definition.", i);
-                 }
-             })
-        .map_err(|e| {
-                     println!("err1 {:?}", e);
-                 })
-        .collect()
-        .map(|_| {
-                 println!("Done.");
-             })
-        .map_err(|e| {
-                     println!("Failed {:?}.", e);
-                 });
+fn read_checkpoint() -> u64 {
+    let mut text = String::new();
+    match File::open(CHECKPOINT_FILE) {
+        Ok(mut file) => {
+            file.read_to_string(&mut text).unwrap();
+            text.trim().parse().unwrap_or(0)
+        }
+        Err(_) => 0,
+    }
+}
 
-    reactor.run(server).unwrap();
+fn write_checkpoint(line: u64) {
+    // Write then rename, so a crash never leaves a half-written checkpoint.
+    let temp = format!("{}.tmp", CHECKPOINT_FILE);
+    let mut file = File::create(&temp).unwrap();
+    writeln!(file, "{}", line).unwrap();
+    file.sync_all().unwrap();
+    fs::rename(&temp, CHECKPOINT_FILE).unwrap();
 }
@@ -0,0 +1,45 @@
+extern crate tarpc;
+extern crate chord;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::net::SocketAddr;
+use tarpc::future::client;
+use tarpc::future::client::ClientExt;
+use tarpc::futures::Future;
+use tarpc::tokio_core::reactor;
+use chord::*;
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() != 3 || (args[1] != "save" && args[1] != "restore") {
+        println!("Usage: {} save|restore SNAPSHOT_FILE", args[0]);
+        return;
+    }
+
+    let addr: SocketAddr = "0.0.0.0:4646".parse().unwrap();
+    let mut reactor = reactor::Core::new().unwrap();
+    let client = FutureClient::connect(addr, client::Options::default())
+        .wait()
+        .unwrap();
+
+    if args[1] == "save" {
+        let file = BufWriter::new(File::create(&args[2]).unwrap());
+        let (_, count) = reactor.run(write_snapshot(client, file, 100)).unwrap();
+        println!("Saved {} terms to {}.", count, args[2]);
+    } else {
+        let file = BufReader::new(File::open(&args[2]).unwrap());
+        let report = reactor.run(restore_snapshot(file, client, 100, 32)).unwrap();
+        for rejection in &report.rejected {
+            println!("Rejected line {}: {}", rejection.line, rejection.reason);
+        }
+        for rejection in &report.unstored {
+            println!("Could not store line {}: {}", rejection.line, rejection.reason);
+        }
+        println!("Restored {} definitions, rejected {}, could not store {}.",
+                 report.imported,
+                 report.rejected.len(),
+                 report.unstored.len());
+    }
+}
@@ -0,0 +1,88 @@
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use futures::{future, Future};
+use futures::future::{Either, Loop};
+use tokio_timer::Timer;
+use slog::Logger;
+use super::*;
+
+/// Resolve seeds given as `ip:port` or `host:port` to the nodes they name, in order.
+/// Seeds that do not resolve are left out, as they may resolve on a later attempt.
+pub fn resolve_seeds(seeds: &[String]) -> Vec<Id> {
+    seeds
+        .iter()
+        .flat_map(|seed| {
+                      seed.as_str()
+                          .to_socket_addrs()
+                          .map(|addrs| addrs.collect::<Vec<_>>())
+                          .unwrap_or_default()
+                  })
+        .map(Id::from)
+        .collect()
+}
+
+/// Ask `node`, known to the ring as `own_id`, to join it through each of `seed_ids` in
+/// turn, stopping at the first that works. A node listed among its own seeds is skipped.
+fn join_any(node: FutureClient,
+            own_id: Id,
+            seed_ids: Vec<Id>)
+            -> Box<Future<Item = Option<Id>, Error = ()>> {
+    let seed_ids = seed_ids
+        .into_iter()
+        .filter(|&seed_id| seed_id != own_id)
+        .collect::<Vec<_>>();
+    box future::loop_fn(seed_ids.into_iter(), move |mut seed_ids| {
+        let seed_id = match seed_ids.next() {
+            Some(seed_id) => seed_id,
+            None => return Either::A(future::ok(Loop::Break(None))),
+        };
+        Either::B(node.join(seed_id)
+                      .then(move |joined| if let Ok(true) = joined {
+                                Ok::<_, ()>(Loop::Break(Some(seed_id)))
+                            } else {
+                                Ok(Loop::Continue(seed_ids))
+                            }))
+    })
+}
+
+/// Join `node`, known to the ring as `own_id`, through the first of `seeds` that accepts
+/// it. Seeds are resolved afresh and tried in order up to `retries` more times, waiting
+/// `backoff` before the first retry and twice as long before each one after. Resolves to
+/// the seed joined through.
+pub fn join_seeds(node: FutureClient,
+                  own_id: Id,
+                  seeds: Vec<String>,
+                  retries: u32,
+                  backoff: Duration,
+                  log: Logger)
+                  -> Box<Future<Item = Id, Error = String>> {
+    let timer = Timer::default();
+    box future::loop_fn((0, backoff), move |(attempt, delay)| {
+        let timer = timer.clone();
+        let log = log.clone();
+        let seed_ids = resolve_seeds(&seeds);
+        if seed_ids.is_empty() {
+            warn!(log, "No seeds resolved"; "seeds" => seeds.join(","));
+        }
+        join_any(node.clone(), own_id, seed_ids)
+            .map_err(|_| "Join failed.".to_string())
+            .and_then(move |joined| {
+                if let Some(seed_id) = joined {
+                    return Either::A(future::ok(Loop::Break(seed_id)));
+                }
+                if attempt >= retries {
+                    let e = format!("No seed accepted the join after {} attempts.", attempt + 1);
+                    return Either::A(future::err(e));
+                }
+                warn!(log, "No seed accepted the join, retrying";
+                      "attempt" => attempt + 1,
+                      "backoff_ms" => delay.as_secs() * 1000 +
+                                      (delay.subsec_nanos() / 1_000_000) as u64);
+                let next = (attempt + 1, delay * 2);
+                Either::B(timer
+                              .sleep(delay)
+                              .map_err(|_| "Timer failed.".to_string())
+                              .map(move |_| Loop::Continue(next)))
+            })
+    })
+}
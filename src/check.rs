@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use super::*;
+
+/// A broken ring invariant found by `check_ring`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Violation {
+    /// A node with no predecessor or successor, in a ring of more than one node.
+    Detached { node: Id },
+    /// `node`'s successor does not name `node` as its predecessor.
+    PredecessorMismatch {
+        node: Id,
+        successor: Id,
+        successors_predecessor: Option<Id>,
+    },
+    /// Keys go down from `node` to its successor, other than from the highest-keyed node
+    /// back round to the lowest.
+    OutOfOrder { node: Id, successor: Id },
+    /// Following successors from `first` ends at `last` without leading back.
+    Unclosed { first: Id, last: Id },
+    /// `node` stores an item under a key it does not own.
+    Misplaced { node: Id, key: Key },
+    /// `node` did not answer, so nothing about it could be checked.
+    Unreachable { node: Id },
+}
+
+impl Violation {
+    pub fn describe(&self) -> String {
+        match *self {
+            Violation::Detached { node } => {
+                format!("{} has no predecessor or successor", node.addr)
+            }
+            Violation::PredecessorMismatch {
+                node,
+                successor,
+                successors_predecessor,
+            } => {
+                let predecessor = successors_predecessor
+                    .map_or("unset".to_string(), |id| id.addr.to_string());
+                format!("{} has successor {}, whose predecessor is {}",
+                        node.addr,
+                        successor.addr,
+                        predecessor)
+            }
+            Violation::OutOfOrder { node, successor } => {
+                format!("{} ({}) is followed by lower-keyed {} ({})",
+                        node.addr,
+                        &key_hex(&node.key)[..8],
+                        successor.addr,
+                        &key_hex(&successor.key)[..8])
+            }
+            Violation::Unclosed { first, last } => {
+                format!("following successors from {} ends at {} without leading back",
+                        first.addr,
+                        last.addr)
+            }
+            Violation::Misplaced { node, key } => {
+                format!("{} stores {}, which it does not own", node.addr, key_hex(&key))
+            }
+            Violation::Unreachable { node } => format!("{} did not answer", node.addr),
+        }
+    }
+}
+
+/// Check a ring as the `topology` RPC found it against the invariants lookups rely on:
+/// each successor points back through its predecessor, keys increase around the ring
+/// with a single wrap, following successors leads back to the start, no node stores keys
+/// it does not own, and every node answers. `misplaced` holds each node's
+/// `misplaced_keys`; nodes missing from it are not checked for placement.
+pub fn check_ring(topology: &Topology, misplaced: &HashMap<Id, Vec<Key>>) -> Vec<Violation> {
+    let metas = &topology.nodes;
+    let mut violations = topology
+        .unreachable
+        .iter()
+        .map(|&node| Violation::Unreachable { node: node })
+        .collect::<Vec<_>>();
+    let by_id = metas
+        .iter()
+        .map(|meta| (meta.id, meta))
+        .collect::<HashMap<_, _>>();
+    let highest = metas.iter().map(|meta| meta.id).max();
+
+    for meta in metas {
+        let relations = match meta.relations {
+            Some(relations) => relations,
+            None => {
+                if metas.len() > 1 {
+                    violations.push(Violation::Detached { node: meta.id });
+                }
+                continue;
+            }
+        };
+        let successor_id = relations.successor_id;
+        if let Some(successor) = by_id.get(&successor_id) {
+            let successors_predecessor = successor
+                .relations
+                .map(|relations| relations.predecessor_id);
+            if successors_predecessor != Some(meta.id) {
+                violations.push(Violation::PredecessorMismatch {
+                                    node: meta.id,
+                                    successor: successor_id,
+                                    successors_predecessor: successors_predecessor,
+                                });
+            }
+        }
+        if successor_id.key < meta.id.key && Some(meta.id) != highest {
+            violations.push(Violation::OutOfOrder {
+                                node: meta.id,
+                                successor: successor_id,
+                            });
+        }
+    }
+
+    if let (Some(first), Some(last)) = (metas.first(), metas.last()) {
+        let closed = last.relations
+            .map_or(metas.len() == 1, |relations| relations.successor_id == first.id);
+        if !closed {
+            violations.push(Violation::Unclosed {
+                                first: first.id,
+                                last: last.id,
+                            });
+        }
+    }
+
+    for meta in metas {
+        for key in misplaced.get(&meta.id).into_iter().flat_map(|keys| keys) {
+            violations.push(Violation::Misplaced {
+                                node: meta.id,
+                                key: *key,
+                            });
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u32) -> Id {
+        Id {
+            addr: format!("127.0.0.1:{}", n).parse().unwrap(),
+            key: [n, 0, 0, 0, 0],
+        }
+    }
+
+    fn meta(n: u32, predecessor: u32, successor: u32) -> NodeMeta<Id> {
+        NodeMeta {
+            id: id(n),
+            relations: Some(NodeRelations {
+                                predecessor_id: id(predecessor),
+                                successor_id: id(successor),
+                            }),
+            itemcount: 0,
+        }
+    }
+
+    fn topology(nodes: Vec<NodeMeta<Id>>) -> Topology {
+        Topology {
+            nodes: nodes,
+            unreachable: vec![],
+        }
+    }
+
+    fn check(topology: &Topology) -> Vec<Violation> {
+        check_ring(topology, &HashMap::new())
+    }
+
+    fn healthy() -> Topology {
+        topology(vec![meta(100, 300, 200), meta(200, 100, 300), meta(300, 200, 100)])
+    }
+
+    #[test]
+    fn a_healthy_ring_has_no_violations() {
+        assert_eq!(check(&healthy()), vec![]);
+        let lone = NodeMeta {
+            id: id(100),
+            relations: None,
+            itemcount: 0,
+        };
+        assert_eq!(check(&topology(vec![lone])), vec![]);
+    }
+
+    #[test]
+    fn a_ring_starting_past_the_wrap_has_no_violations() {
+        let nodes = vec![meta(200, 100, 300), meta(300, 200, 100), meta(100, 300, 200)];
+        assert_eq!(check(&topology(nodes)), vec![]);
+    }
+
+    #[test]
+    fn detached_nodes_are_reported() {
+        let mut ring = healthy();
+        ring.nodes[1].relations = None;
+        assert!(check(&ring).contains(&Violation::Detached { node: id(200) }));
+    }
+
+    #[test]
+    fn successors_not_pointing_back_are_reported() {
+        let mut ring = healthy();
+        ring.nodes[1] = meta(200, 300, 300);
+        assert!(check(&ring).contains(&Violation::PredecessorMismatch {
+                                           node: id(100),
+                                           successor: id(200),
+                                           successors_predecessor: Some(id(300)),
+                                       }));
+    }
+
+    #[test]
+    fn keys_going_down_other_than_at_the_wrap_are_reported() {
+        // Down from the highest node back round to the lowest is the one wrap allowed.
+        let nodes = vec![meta(100, 200, 300), meta(300, 100, 200), meta(200, 300, 100)];
+        let violations = check(&topology(nodes));
+        assert!(violations.contains(&Violation::OutOfOrder {
+                                        node: id(200),
+                                        successor: id(100),
+                                    }));
+        assert_eq!(violations.len(), 1);
+    }
+
+    #[test]
+    fn rings_that_do_not_lead_back_are_reported() {
+        let mut ring = healthy();
+        ring.nodes[2] = meta(300, 200, 400);
+        assert!(check(&ring).contains(&Violation::Unclosed {
+                                           first: id(100),
+                                           last: id(300),
+                                       }));
+    }
+
+    #[test]
+    fn misplaced_keys_are_reported() {
+        let mut misplaced = HashMap::new();
+        misplaced.insert(id(200), vec![[250, 0, 0, 0, 0]]);
+        misplaced.insert(id(300), vec![]);
+        assert_eq!(check_ring(&healthy(), &misplaced),
+                   vec![Violation::Misplaced {
+                            node: id(200),
+                            key: [250, 0, 0, 0, 0],
+                        }]);
+    }
+
+    #[test]
+    fn nodes_that_did_not_answer_are_reported() {
+        let mut ring = healthy();
+        ring.unreachable.push(id(400));
+        assert_eq!(check(&ring), vec![Violation::Unreachable { node: id(400) }]);
+    }
+
+    #[test]
+    fn violations_are_described_by_address() {
+        let violation = Violation::Unclosed {
+            first: id(100),
+            last: id(300),
+        };
+        assert_eq!(violation.describe(),
+                   "following successors from 127.0.0.1:100 ends at 127.0.0.1:300 without \
+                    leading back");
+    }
+}
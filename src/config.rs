@@ -0,0 +1,206 @@
+use std::env;
+use std::io;
+use std::io::prelude::*;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use serde_json;
+use toml;
+use slog::Level;
+use super::*;
+
+/// Names the config file when no path is given on the command line.
+pub const CONFIG_PATH_VAR: &'static str = "CHORD_CONFIG";
+
+/// Everything a node needs to start. Read from a TOML file, or JSON if the file name ends
+/// in `.json`, with any field overridden by its `CHORD_*` environment variable. Missing
+/// fields take their defaults.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address to bind. `CHORD_LISTEN`.
+    pub listen: SocketAddr,
+    /// Address peers should use to reach this node, when it differs from `listen`.
+    /// `CHORD_ADVERTISE`.
+    pub advertise: Option<SocketAddr>,
+    /// Address to serve HTTP on: `/metrics` and the JSON gateway.
+    /// `CHORD_HTTP_LISTEN`, empty for none.
+    pub http_listen: Option<SocketAddr>,
+    /// Seed nodes to join through, as `ip:port` or `host:port`, tried in order.
+    /// `CHORD_BOOTSTRAP`, comma-separated.
+    pub bootstrap: Vec<String>,
+    /// Further rounds through the seeds when none accepts the join.
+    /// `CHORD_JOIN_RETRIES`.
+    pub join_retries: u32,
+    /// Wait before the first retry, doubling after each. `CHORD_JOIN_BACKOFF_SECS`.
+    pub join_backoff_secs: u64,
+    /// Copies kept of each item. Only 1 is supported. `CHORD_REPLICATION_FACTOR`.
+    pub replication_factor: usize,
+    /// How long a forwarded request may take. `CHORD_REQUEST_TIMEOUT_SECS`.
+    pub request_timeout_secs: u64,
+    /// How often expired items and tombstones are collected. `CHORD_SWEEP_INTERVAL_SECS`.
+    pub sweep_interval_secs: u64,
+    /// How long a deleted item's tombstone is kept before it is collected. It must outlive
+    /// any stale copy of the item. `CHORD_TOMBSTONE_GRACE_SECS`.
+    pub tombstone_grace_secs: u64,
+    /// Least severe level logged at startup: trace, debug, info, warning, error or
+    /// critical. `CHORD_LOG_LEVEL`.
+    pub log_level: String,
+    /// `term` or `json`. `CHORD_LOG_FORMAT`.
+    pub log_format: LogFormat,
+    /// How the HTTP gateway turns terms into keys. It must match what the ring was loaded
+    /// with. `CHORD_NORMALIZATION`, as a comma-separated list of steps or `none`.
+    pub normalization: Normalization,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: "0.0.0.0:4646".parse().unwrap(),
+            advertise: None,
+            http_listen: None,
+            bootstrap: vec![],
+            join_retries: 5,
+            join_backoff_secs: 1,
+            replication_factor: 1,
+            request_timeout_secs: 8,
+            sweep_interval_secs: 60,
+            tombstone_grace_secs: DEFAULT_TOMBSTONE_GRACE_SECS,
+            log_level: "info".to_string(),
+            log_format: LogFormat::Term,
+            normalization: Normalization::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+fn parse_env<T>(name: &str, value: &str) -> Result<T, ConfigError>
+    where T: FromStr
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ConfigError::Invalid(format!("Invalid {}: {:?}", name, value)))
+}
+
+fn env_override<T>(name: &str, field: &mut T) -> Result<(), ConfigError>
+    where T: FromStr
+{
+    if let Ok(value) = env::var(name) {
+        *field = parse_env(name, &value)?;
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Read the file at `path` if given, then apply environment overrides.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        if path.extension().map_or(false, |extension| extension == "json") {
+            serde_json::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))
+        } else {
+            toml::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))
+        }
+    }
+
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        env_override("CHORD_LISTEN", &mut self.listen)?;
+        if let Ok(value) = env::var("CHORD_ADVERTISE") {
+            self.advertise = if value.trim().is_empty() {
+                None
+            } else {
+                Some(parse_env("CHORD_ADVERTISE", &value)?)
+            };
+        }
+        if let Ok(value) = env::var("CHORD_HTTP_LISTEN") {
+            self.http_listen = if value.trim().is_empty() {
+                None
+            } else {
+                Some(parse_env("CHORD_HTTP_LISTEN", &value)?)
+            };
+        }
+        if let Ok(value) = env::var("CHORD_BOOTSTRAP") {
+            self.bootstrap = value
+                .split(',')
+                .map(|seed| seed.trim().to_string())
+                .filter(|seed| !seed.is_empty())
+                .collect();
+        }
+        env_override("CHORD_JOIN_RETRIES", &mut self.join_retries)?;
+        env_override("CHORD_JOIN_BACKOFF_SECS", &mut self.join_backoff_secs)?;
+        env_override("CHORD_REPLICATION_FACTOR", &mut self.replication_factor)?;
+        env_override("CHORD_REQUEST_TIMEOUT_SECS", &mut self.request_timeout_secs)?;
+        env_override("CHORD_SWEEP_INTERVAL_SECS", &mut self.sweep_interval_secs)?;
+        env_override("CHORD_TOMBSTONE_GRACE_SECS", &mut self.tombstone_grace_secs)?;
+        env_override("CHORD_LOG_LEVEL", &mut self.log_level)?;
+        env_override("CHORD_LOG_FORMAT", &mut self.log_format)?;
+        env_override("CHORD_NORMALIZATION", &mut self.normalization)?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.replication_factor != 1 {
+            return Err(ConfigError::Invalid(format!("replication_factor {} is not supported, \
+                                                     items are kept on one node only.",
+                                                    self.replication_factor)));
+        }
+        if self.request_timeout_secs == 0 || self.sweep_interval_secs == 0 ||
+           self.tombstone_grace_secs == 0 {
+            return Err(ConfigError::Invalid("Timeouts and intervals must be at least a second."
+                                                .to_string()));
+        }
+        if self.log_level.parse::<Level>().is_err() {
+            return Err(ConfigError::Invalid(format!("Unknown log_level {:?}.", self.log_level)));
+        }
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> Level {
+        self.log_level.parse().unwrap_or(Level::Info)
+    }
+
+    pub fn addrs(&self) -> NodeAddrs {
+        NodeAddrs::new(self.listen, self.advertise)
+    }
+
+    pub fn join_backoff(&self) -> Duration {
+        Duration::from_secs(self.join_backoff_secs)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval_secs)
+    }
+
+    pub fn tombstone_grace(&self) -> Duration {
+        Duration::from_secs(self.tombstone_grace_secs)
+    }
+}
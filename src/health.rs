@@ -0,0 +1,38 @@
+use super::*;
+
+/// Where a node is in its life, as far as whoever routes traffic to it is concerned.
+/// Worked out afresh from the node's relations on every health check, short of
+/// `Leaving`, which only `drain` sets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    /// Joining a ring through a seed, or still waiting for one to accept it.
+    Joining,
+    /// Standing alone, or part of a ring and able to reach its successor.
+    Ready,
+    /// Drained ahead of stopping. It still answers, but should get no new traffic. There
+    /// is no way back: a drained node is meant to be stopped, and restarted to rejoin.
+    Leaving,
+    /// Part of a ring, but its successor did not answer.
+    Degraded,
+}
+
+/// What the `health` RPC reports about a node.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Health {
+    pub state: HealthState,
+    pub relations: Option<NodeRelations<Id>>,
+    /// Whether the successor answered just now, if it was asked: it is only asked of a
+    /// node in a ring with others that is not leaving.
+    pub successor_reachable: Option<bool>,
+    /// Whether keys are moving just now between the node and a node joining next to it,
+    /// as the node joins or as it takes the joining node in.
+    pub transferring: bool,
+}
+
+impl Health {
+    /// Whether the node should be sent traffic.
+    pub fn is_ready(&self) -> bool {
+        self.state == HealthState::Ready
+    }
+}
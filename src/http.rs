@@ -0,0 +1,257 @@
+use std::io;
+use std::net::SocketAddr;
+use futures::{future, Future, Stream};
+use futures::future::Either;
+use hyper;
+use hyper::{Body, Method, StatusCode};
+use hyper::header::ContentType;
+use hyper::server::{Http, Request, Response, Service};
+use percent_encoding::percent_decode;
+use serde::Serialize;
+use serde_json;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+use slog::Logger;
+use super::*;
+
+/// Serve `service` over HTTP on `addr`. Spawn the result on the reactor.
+pub fn serve_http<S>(addr: &SocketAddr,
+                     handle: &Handle,
+                     service: S,
+                     log: Logger)
+                     -> io::Result<Box<Future<Item = (), Error = ()>>>
+    where S: Service<Request = Request, Response = Response, Error = hyper::Error> + Clone + 'static
+{
+    let listener = TcpListener::bind(addr, handle)?;
+    let http = Http::new();
+    let handle = handle.clone();
+    Ok(box listener
+               .incoming()
+               .for_each(move |(socket, peer)| {
+                             http.bind_connection(&handle, socket, peer, service.clone());
+                             Ok(())
+                         })
+               .map_err(move |e| {
+                            error!(log, "HTTP server stopped"; "error" => e.to_string());
+                        }))
+}
+
+const DEFINITIONS_PATH: &'static str = "/definitions/";
+
+type ResponseFuture = Box<Future<Item = Response, Error = hyper::Error>>;
+
+fn json_response<T: Serialize>(value: &T) -> Response {
+    match serde_json::to_string(value) {
+        Ok(json) => Response::new().with_header(ContentType::json()).with_body(json),
+        Err(e) => error_response(StatusCode::InternalServerError, e.to_string()),
+    }
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    Response::new()
+        .with_status(status)
+        .with_header(ContentType::plaintext())
+        .with_body(message + "\n")
+}
+
+/// 504 if the ring timed out answering, 502 for anything else that went wrong past the
+/// gateway.
+fn request_failed(e: ::tarpc::Error<TimeoutErr<bool>>) -> Response {
+    let status = match e {
+        ::tarpc::Error::App(TimeoutErr::TimedOut) => StatusCode::GatewayTimeout,
+        _ => StatusCode::BadGateway,
+    };
+    error_response(status, format!("Request failed: {:?}", e))
+}
+
+fn no_definition(term: &str) -> Response {
+    error_response(StatusCode::NotFound, format!("No definition of {:?}.", term))
+}
+
+/// Reply to `rpc` with whatever `respond` makes of its result, or with why it failed.
+fn reply<F, R>(rpc: F, respond: R) -> ResponseFuture
+    where F: Future<Error = ::tarpc::Error<TimeoutErr<bool>>> + 'static,
+          R: FnOnce(F::Item) -> Response + 'static
+{
+    box rpc.then(move |result| {
+                          Ok::<_, hyper::Error>(match result {
+                                                    Ok(item) => respond(item),
+                                                    Err(e) => request_failed(e),
+                                                })
+                      })
+}
+
+/// Reply with the JSON of whatever `rpc` resolves to.
+fn answer<F>(rpc: F) -> ResponseFuture
+    where F: Future<Error = ::tarpc::Error<TimeoutErr<bool>>> + 'static,
+          F::Item: Serialize
+{
+    reply(rpc, |value| json_response(&value))
+}
+
+/// 200 for a ready node and 503 otherwise, so load balancers and orchestrators can act
+/// on the status alone.
+fn health_response(health: Health) -> Response {
+    let status = if health.is_ready() {
+        StatusCode::Ok
+    } else {
+        StatusCode::ServiceUnavailable
+    };
+    json_response(&health).with_status(status)
+}
+
+/// A definition sent as the body of `PUT /definitions/{term}`, in the same shape `GET`
+/// returns. It must be of the term in the path, so it can be found there again.
+fn parse_definition(body: &[u8],
+                    key: Key,
+                    normalization: &Normalization)
+                    -> Result<Definition, String> {
+    let definition = serde_json::from_slice::<Definition>(body)
+        .map_err(|e| format!("Invalid definition: {}", e))?;
+    definition.validate()?;
+    if term_key(&definition.canonical_term, normalization) != key {
+        return Err(format!("Definition is of {:?}, not the term in the path.",
+                           definition.canonical_term));
+    }
+    Ok(definition)
+}
+
+/// Pass a request on `/definitions/{term}` on to the ring, finding the term under
+/// `normalization`.
+fn definition(client: &FutureClient,
+              normalization: Normalization,
+              method: Method,
+              term: String,
+              body: Body)
+              -> ResponseFuture {
+    let key = term_key(&term, &normalization);
+    match method {
+        Method::Get => {
+            reply(client.get(key), move |definition| match definition {
+                Some(definition) => json_response(&definition),
+                None => no_definition(&term),
+            })
+        }
+        Method::Put => {
+            let client = client.clone();
+            box body.concat2()
+                    .and_then(move |body| match parse_definition(&body, key, &normalization) {
+                                  Ok(definition) => {
+                                      let stored = definition.clone();
+                                      Either::A(reply(client.set(key, stored),
+                                                      move |()| json_response(&definition)))
+                                  }
+                                  Err(e) => {
+                                      let response = error_response(StatusCode::BadRequest, e);
+                                      Either::B(future::ok(response))
+                                  }
+                              })
+        }
+        Method::Delete => {
+            reply(client.delete(key), move |deleted| if deleted {
+                Response::new().with_status(StatusCode::NoContent)
+            } else {
+                no_definition(&term)
+            })
+        }
+        _ => box future::ok(Response::new().with_status(StatusCode::MethodNotAllowed)),
+    }
+}
+
+/// The HTTP side of a node, or a gateway standing in for one. `GET /metrics` gives a
+/// node's metrics in the Prometheus text format and `GET /health` what its `health` RPC
+/// reports, or for a standalone gateway that of the node it passes requests on to. With
+/// a gateway client, `GET`, `PUT` and `DELETE` on `/definitions/{term}`, and `GET` on
+/// `/meta` and `/ring`, are passed on to the ring and answered in JSON.
+#[derive(Clone)]
+pub struct HttpService {
+    chord_server: Option<ChordServer>,
+    gateway: Option<FutureClient>,
+    normalization: Normalization,
+}
+
+impl HttpService {
+    pub fn new(chord_server: ChordServer) -> HttpService {
+        HttpService {
+            chord_server: Some(chord_server),
+            gateway: None,
+            normalization: Normalization::default(),
+        }
+    }
+
+    /// A standalone gateway, passing requests on to the ring through `client`.
+    pub fn proxy(client: FutureClient) -> HttpService {
+        HttpService {
+            chord_server: None,
+            gateway: Some(client),
+            normalization: Normalization::default(),
+        }
+    }
+
+    /// Also serve the gateway routes, through `client`.
+    pub fn gateway(mut self, client: FutureClient) -> HttpService {
+        self.gateway = Some(client);
+        self
+    }
+
+    /// How terms in `/definitions/{term}` are turned into keys. It must match what the
+    /// ring was loaded with.
+    pub fn normalization(mut self, normalization: Normalization) -> HttpService {
+        self.normalization = normalization;
+        self
+    }
+}
+
+impl Service for HttpService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn call(&self, request: Request) -> Self::Future {
+        let (method, path) = (request.method().clone(), request.path().to_string());
+        match (&method, path.as_str(), &self.chord_server, &self.gateway) {
+            (&Method::Get, "/metrics", &Some(ref chord_server), _) => {
+                box future::ok(Response::new()
+                                   .with_header(ContentType::plaintext())
+                                   .with_body(chord_server.render_metrics()))
+            }
+            (&Method::Get, "/health", &Some(ref chord_server), _) => {
+                box FutureService::health(chord_server)
+                        .map(health_response)
+                        .or_else(|_| {
+                                     let response = Response::new()
+                                         .with_status(StatusCode::InternalServerError);
+                                     Ok::<_, hyper::Error>(response)
+                                 })
+            }
+            (&Method::Get, "/health", &None, &Some(ref client)) => {
+                reply(client.health(), health_response)
+            }
+            (&Method::Get, "/meta", _, &Some(ref client)) => answer(client.meta()),
+            (&Method::Get, "/ring", _, &Some(ref client)) => answer(client.topology()),
+            (_, path, _, &Some(ref client)) if path.starts_with(DEFINITIONS_PATH) => {
+                let term = percent_decode(path[DEFINITIONS_PATH.len()..].as_bytes())
+                    .decode_utf8()
+                    .map(|term| term.into_owned());
+                match term {
+                    Ok(ref term) if term.is_empty() => {
+                        box future::ok(Response::new().with_status(StatusCode::NotFound))
+                    }
+                    Ok(term) => {
+                        definition(client,
+                                   self.normalization,
+                                   method.clone(),
+                                   term,
+                                   request.body())
+                    }
+                    Err(_) => {
+                        let message = "Term is not valid UTF-8.".to_string();
+                        box future::ok(error_response(StatusCode::BadRequest, message))
+                    }
+                }
+            }
+            _ => box future::ok(Response::new().with_status(StatusCode::NotFound)),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::io;
+use std::collections::HashMap;
+use std::time::Duration;
+use csv;
+use futures::{future, stream, Future, Stream};
+use futures::future::{Either, Loop};
+use tokio_timer::Timer;
+use super::*;
+
+/// A row that could not be imported, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Rows that are not valid definitions.
+    pub rejected: Vec<Rejection>,
+    /// Valid rows that could not be stored, even after retries. Loading them again may
+    /// yet work.
+    pub unstored: Vec<Rejection>,
+    /// Batch attempts that failed, whether or not a retry then succeeded.
+    pub failed_attempts: usize,
+}
+
+impl ImportReport {
+    pub fn absorb(&mut self, other: ImportReport) {
+        self.imported += other.imported;
+        self.rejected.extend(other.rejected);
+        self.unstored.extend(other.unstored);
+        self.failed_attempts += other.failed_attempts;
+    }
+}
+
+pub enum Row {
+    Accepted {
+        line: u64,
+        key: Key,
+        definition: Definition,
+        /// When the definition expires, if it does.
+        expires_at: Option<Timestamp>,
+    },
+    Rejected(Rejection),
+}
+
+impl Row {
+    pub fn line(&self) -> u64 {
+        match *self {
+            Row::Accepted { line, .. } => line,
+            Row::Rejected(ref rejection) => rejection.line,
+        }
+    }
+
+    /// Validate a parsed definition from `line` and work out its key.
+    pub fn new(line: u64, definition: Definition, normalization: &Normalization) -> Row {
+        match definition.validate() {
+            Ok(()) => {
+                Row::Accepted {
+                    line: line,
+                    key: term_key(&definition.canonical_term, normalization),
+                    definition: definition,
+                    expires_at: None,
+                }
+            }
+            Err(reason) => Row::Rejected(Rejection { line, reason }),
+        }
+    }
+}
+
+/// Reads definitions from CSV one record at a time, turning rows that fail to parse or
+/// validate into rejections rather than dropping them.
+pub struct CsvRows<R> {
+    reader: csv::Reader<R>,
+    headers: csv::StringRecord,
+    normalization: Normalization,
+    done: bool,
+}
+
+impl<R> CsvRows<R>
+    where R: io::Read
+{
+    pub fn new(reader: R, normalization: Normalization) -> csv::Result<CsvRows<R>> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+        Ok(CsvRows {
+               reader: reader,
+               headers: headers,
+               normalization: normalization,
+               done: false,
+           })
+    }
+
+    /// The line the reader has got to, for errors that do not say where they happened.
+    fn line(&self) -> u64 {
+        self.reader.position().line()
+    }
+
+    fn row(&self, record: &csv::StringRecord) -> Row {
+        let line = record
+            .position()
+            .map_or_else(|| self.line(), |position| position.line());
+        match record.deserialize::<Definition>(Some(&self.headers)) {
+            Ok(definition) => Row::new(line, definition, &self.normalization),
+            Err(e) => {
+                Row::Rejected(Rejection {
+                                  line: line,
+                                  reason: e.to_string(),
+                              })
+            }
+        }
+    }
+}
+
+impl<R> Iterator for CsvRows<R>
+    where R: io::Read
+{
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        if self.done {
+            return None;
+        }
+        let mut record = csv::StringRecord::new();
+        match self.reader.read_record(&mut record) {
+            Ok(true) => Some(self.row(&record)),
+            Ok(false) => None,
+            Err(e) => {
+                // The reader can carry on past malformed rows, but not past I/O errors.
+                self.done = e.is_io_error();
+                let line = e.position()
+                    .map_or_else(|| self.line(), |position| position.line());
+                Some(Row::Rejected(Rejection {
+                                       line: line,
+                                       reason: e.to_string(),
+                                   }))
+            }
+        }
+    }
+}
+
+/// Stream CSV definitions from `reader` into the ring through `client`. See `import_rows`.
+pub fn import_csv<R>(reader: R,
+                     client: FutureClient,
+                     normalization: Normalization,
+                     batch_size: usize,
+                     concurrency: usize)
+                     -> Box<Future<Item = ImportReport, Error = ()>>
+    where R: io::Read + 'static
+{
+    match CsvRows::new(reader, normalization) {
+        Ok(rows) => import_rows(rows, client, batch_size, concurrency),
+        Err(e) => {
+            box future::ok(ImportReport {
+                               imported: 0,
+                               rejected: vec![Rejection {
+                                                  line: 1,
+                                                  reason: e.to_string(),
+                                              }],
+                               ..ImportReport::default()
+                           })
+        }
+    }
+}
+
+/// How `load_rows` pushes rows into the ring.
+#[derive(Clone, Debug)]
+pub struct LoadOptions {
+    /// Rows per `set_many`.
+    pub batch_size: usize,
+    /// Batches in flight at once.
+    pub concurrency: usize,
+    /// How many times a failed batch is retried, waiting `backoff` before the first retry
+    /// and twice as long before each one after.
+    pub retries: u32,
+    pub backoff: Duration,
+    /// Rows on or before this line are skipped, to resume from a checkpoint.
+    pub resume_after: u64,
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            batch_size: 100,
+            concurrency: 32,
+            retries: 0,
+            backoff: Duration::from_millis(100),
+            resume_after: 0,
+        }
+    }
+}
+
+/// Push `rows` into the ring through `client`, `batch_size` rows per `set_many` and with
+/// at most `concurrency` batches in flight.
+pub fn import_rows<I>(rows: I,
+                      client: FutureClient,
+                      batch_size: usize,
+                      concurrency: usize)
+                      -> Box<Future<Item = ImportReport, Error = ()>>
+    where I: Iterator<Item = Row> + 'static
+{
+    let options = LoadOptions {
+        batch_size: batch_size,
+        concurrency: concurrency,
+        ..LoadOptions::default()
+    };
+    load_rows(rows, client, &options, |_| {})
+}
+
+/// The TTL that makes an item sent now expire at `expires_at`.
+fn ttl_until(expires_at: Timestamp) -> Duration {
+    Duration::from_millis(expires_at.saturating_sub(timestamp()))
+}
+
+/// Send one batch, retrying the keys that were not stored with exponential backoff.
+/// Expiring items get their TTL afresh on every attempt, so they still expire on time.
+/// Resolves to the number of failed attempts, and to the keys still not stored once
+/// retries run out, each with the last error.
+fn set_many_with_retries(client: FutureClient,
+                         timer: Timer,
+                         items: Vec<(Key, Definitions, Option<Timestamp>)>,
+                         retries: u32,
+                         backoff: Duration)
+                         -> Box<Future<Item = (usize, HashMap<Key, String>), Error = ()>> {
+    box future::loop_fn((items, 0, backoff), move |(items, failures, delay)| {
+        let timer = timer.clone();
+        let batch = items
+            .iter()
+            .map(|&(key, ref definitions, expires_at)| {
+                     (key, definitions.clone(), expires_at.map(ttl_until))
+                 })
+            .collect();
+        client
+            .set_many(batch)
+            .then(move |result| {
+                let failed = match result {
+                    Ok(results) => {
+                        results
+                            .into_iter()
+                            .filter_map(|(key, result)| {
+                                            result.err().map(|e| (key, format!("{:?}", e)))
+                                        })
+                            .collect::<HashMap<_, _>>()
+                    }
+                    Err(e) => {
+                        let reason = format!("{:?}", e);
+                        items.iter().map(|&(key, _, _)| (key, reason.clone())).collect()
+                    }
+                };
+                if failed.is_empty() {
+                    Either::A(future::ok(Loop::Break((failures, failed))))
+                } else if failures as u32 >= retries {
+                    Either::A(future::ok(Loop::Break((failures + 1, failed))))
+                } else {
+                    let items = items
+                        .into_iter()
+                        .filter(|&(key, _, _)| failed.contains_key(&key))
+                        .collect::<Vec<_>>();
+                    let next = (items, failures + 1, delay * 2);
+                    Either::B(timer
+                                  .sleep(delay)
+                                  .then(move |_| Ok::<_, ()>(Loop::Continue(next))))
+                }
+            })
+    })
+}
+
+/// Push `rows` into the ring as described by `options`. After every batch, `checkpoint`
+/// is called with a line such that every row up to it has been stored or rejected;
+/// passing that line back as `resume_after` carries on from there. Once a batch is not
+/// fully stored, the checkpoint stays at the batch before it.
+pub fn load_rows<I, F>(rows: I,
+                       client: FutureClient,
+                       options: &LoadOptions,
+                       mut checkpoint: F)
+                       -> Box<Future<Item = ImportReport, Error = ()>>
+    where I: Iterator<Item = Row> + 'static,
+          F: FnMut(u64) + 'static
+{
+    let timer = Timer::default();
+    let resume_after = options.resume_after;
+    let retries = options.retries;
+    let backoff = options.backoff;
+    box stream::iter_ok::<_, ()>(rows.filter(move |row| row.line() > resume_after))
+            .chunks(options.batch_size)
+            .map(move |rows| {
+                let mut report = ImportReport::default();
+                let last_line = rows.iter().map(|row| row.line()).max().unwrap_or(0);
+                let mut lines = vec![];
+                let mut items = vec![];
+                for row in rows {
+                    match row {
+                        Row::Accepted {
+                            line,
+                            key,
+                            definition,
+                            expires_at,
+                        } => {
+                            lines.push((line, key));
+                            items.push((key, Definitions::from(definition), expires_at));
+                        }
+                        Row::Rejected(rejection) => report.rejected.push(rejection),
+                    }
+                }
+                set_many_with_retries(client.clone(), timer.clone(), items, retries, backoff)
+                    .map(move |(failures, failed)| {
+                        report.failed_attempts += failures;
+                        for (line, key) in lines {
+                            match failed.get(&key) {
+                                None => report.imported += 1,
+                                Some(e) => {
+                                    report
+                                        .unstored
+                                        .push(Rejection {
+                                                  line: line,
+                                                  reason: format!("Could not store row: {}", e),
+                                              })
+                                }
+                            }
+                        }
+                        (last_line, report)
+                    })
+            })
+            .buffered(options.concurrency)
+            .fold((ImportReport::default(), false),
+                  move |(mut report, held), (last_line, batch)| {
+                // Batches arrive in order, so everything up to here is accounted for,
+                // unless some earlier row could not be stored.
+                let held = held || !batch.unstored.is_empty();
+                report.absorb(batch);
+                if !held {
+                    checkpoint(last_line);
+                }
+                Ok::<_, ()>((report, held))
+            })
+            .map(|(mut report, _)| {
+                     report.rejected.sort_by_key(|rejection| rejection.line);
+                     report.unstored.sort_by_key(|rejection| rejection.line);
+                     report
+                 })
+}
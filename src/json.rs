@@ -0,0 +1,240 @@
+use std::io;
+use futures::{future, Future};
+use futures::future::Loop;
+use serde_json;
+use super::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JsonFormat {
+    /// One definition object per line.
+    Ndjson,
+    /// A single array of definition objects.
+    Array,
+}
+
+/// Reads newline-delimited JSON definitions one line at a time. Blank lines are skipped.
+pub struct NdjsonRows<R> {
+    lines: io::Lines<R>,
+    line: u64,
+    normalization: Normalization,
+}
+
+impl<R> NdjsonRows<R>
+    where R: io::BufRead
+{
+    pub fn new(reader: R, normalization: Normalization) -> NdjsonRows<R> {
+        NdjsonRows {
+            lines: reader.lines(),
+            line: 0,
+            normalization: normalization,
+        }
+    }
+}
+
+impl<R> Iterator for NdjsonRows<R>
+    where R: io::BufRead
+{
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        loop {
+            self.line += 1;
+            let text = match self.lines.next() {
+                Some(Ok(text)) => text,
+                Some(Err(e)) => {
+                    return Some(Row::Rejected(Rejection {
+                                                  line: self.line,
+                                                  reason: e.to_string(),
+                                              }))
+                }
+                None => return None,
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+            return Some(match serde_json::from_str::<Definition>(&text) {
+                            Ok(definition) => Row::new(self.line, definition, &self.normalization),
+                            Err(e) => {
+                                Row::Rejected(Rejection {
+                                                  line: self.line,
+                                                  reason: e.to_string(),
+                                              })
+                            }
+                        });
+        }
+    }
+}
+
+/// Parse a JSON array of definitions. Arrays are read whole, and a rejection's `line` is
+/// the 1-based position of the element in the array.
+pub fn json_array_rows<R>(reader: R, normalization: &Normalization) -> serde_json::Result<Vec<Row>>
+    where R: io::Read
+{
+    let values: Vec<serde_json::Value> = serde_json::from_reader(reader)?;
+    Ok(values
+           .into_iter()
+           .enumerate()
+           .map(|(i, value)| {
+                    let position = i as u64 + 1;
+                    match serde_json::from_value::<Definition>(value) {
+                        Ok(definition) => Row::new(position, definition, normalization),
+                        Err(e) => {
+                            Row::Rejected(Rejection {
+                                              line: position,
+                                              reason: e.to_string(),
+                                          })
+                        }
+                    }
+                })
+           .collect())
+}
+
+/// Stream JSON definitions from `reader` into the ring through `client`. See
+/// `import_rows`.
+pub fn import_json<R>(reader: R,
+                      format: JsonFormat,
+                      client: FutureClient,
+                      normalization: Normalization,
+                      batch_size: usize,
+                      concurrency: usize)
+                      -> Box<Future<Item = ImportReport, Error = ()>>
+    where R: io::BufRead + 'static
+{
+    match format {
+        JsonFormat::Ndjson => {
+            import_rows(NdjsonRows::new(reader, normalization),
+                        client,
+                        batch_size,
+                        concurrency)
+        }
+        JsonFormat::Array => {
+            match json_array_rows(reader, &normalization) {
+                Ok(rows) => import_rows(rows.into_iter(), client, batch_size, concurrency),
+                Err(e) => {
+                    box future::ok(ImportReport {
+                                       imported: 0,
+                                       rejected: vec![Rejection {
+                                                          line: e.line() as u64,
+                                                          reason: e.to_string(),
+                                                      }],
+                                       ..ImportReport::default()
+                                   })
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Ring(::tarpc::Error<TimeoutErr<bool>>),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> ExportError {
+        ExportError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> ExportError {
+        ExportError::Io(e.into())
+    }
+}
+
+/// Write one definition, preceded by a separator if it is not the first in an array.
+fn write_definition<W>(writer: &mut W,
+                       definition: &Definition,
+                       format: JsonFormat,
+                       first: bool)
+                       -> Result<(), ExportError>
+    where W: io::Write
+{
+    match format {
+        JsonFormat::Ndjson => {
+            serde_json::to_writer(&mut *writer, definition)?;
+            writer.write_all(b"\n")?;
+        }
+        JsonFormat::Array => {
+            if !first {
+                writer.write_all(b",")?;
+            }
+            writer.write_all(b"\n")?;
+            serde_json::to_writer(&mut *writer, definition)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_end<W>(writer: &mut W, format: JsonFormat) -> Result<(), ExportError>
+    where W: io::Write
+{
+    if format == JsonFormat::Array {
+        writer.write_all(b"\n]\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Visit every page of a scan of the ring in key order, `page_size` terms at a time,
+/// threading `state` through `visit`.
+pub fn walk_ring<S, F>(client: FutureClient,
+                       page_size: usize,
+                       state: S,
+                       visit: F)
+                       -> Box<Future<Item = S, Error = ExportError>>
+    where S: 'static,
+          F: FnMut(S, ScanPage<Key, Definitions>) -> Result<S, ExportError> + 'static
+{
+    let start = ScanBound::Included(MIN_KEY);
+    box future::loop_fn((client, start, state, visit),
+                        move |(client, start, state, mut visit)| {
+        client
+            .scan(start, MAX_KEY, page_size)
+            .map_err(ExportError::Ring)
+            .and_then(move |page| {
+                let next = page.next;
+                let state = match visit(state, page) {
+                    Ok(state) => state,
+                    Err(e) => return Err(e),
+                };
+                Ok(match next {
+                       Some(next) => Loop::Continue((client, next, state, visit)),
+                       None => Loop::Break(state),
+                   })
+            })
+    })
+}
+
+/// Write every definition in the ring to `writer`, in pages of `page_size` terms.
+/// Resolves to the writer and the number of definitions written.
+pub fn export_json<W>(client: FutureClient,
+                      mut writer: W,
+                      format: JsonFormat,
+                      page_size: usize)
+                      -> Box<Future<Item = (W, usize), Error = ExportError>>
+    where W: io::Write + 'static
+{
+    if format == JsonFormat::Array {
+        if let Err(e) = writer.write_all(b"[") {
+            return box future::err(e.into());
+        }
+    }
+
+    box walk_ring(client,
+                  page_size,
+                  (writer, 0),
+                  move |(mut writer, mut count), page| {
+        for (_, definitions) in page.items {
+            for (_, definition) in definitions.by_urban_id {
+                write_definition(&mut writer, &definition, format, count == 0)?;
+                count += 1;
+            }
+        }
+        Ok((writer, count))
+    })
+            .and_then(move |(mut writer, count)| {
+                          write_end(&mut writer, format).map(|_| (writer, count))
+                      })
+}
@@ -5,29 +5,65 @@
 #![feature(box_syntax)]
 
 extern crate csv;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate sha1;
+#[macro_use]
+extern crate slog;
+extern crate slog_async;
+extern crate slog_json;
+extern crate slog_term;
 extern crate rand;
 #[macro_use]
 extern crate tarpc;
 extern crate futures;
+extern crate hyper;
+extern crate percent_encoding;
 extern crate tokio_core;
 extern crate tokio_timer;
+extern crate toml;
+extern crate unicode_normalization;
 
-use std::io;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+mod bootstrap;
+mod check;
+mod config;
+mod health;
 mod rpc;
 mod node;
+mod normalize;
 mod query;
 mod query_engine;
+mod search;
+mod http;
+mod import;
+mod json;
+mod logging;
+mod metrics;
+mod snapshot;
+mod topology;
 pub mod utils;
 
+pub use bootstrap::*;
+pub use check::*;
+pub use config::*;
+pub use health::*;
 pub use rpc::*;
 pub use node::*;
+pub use normalize::*;
 pub use query::*;
 pub use query_engine::*;
+pub use search::*;
+pub use http::*;
+pub use import::*;
+pub use json::*;
+pub use logging::*;
+pub use metrics::*;
+pub use snapshot::*;
+pub use topology::*;
 
 /// List of node IDs, representing the hops from the request node to the target node.
 //pub type Route = Vec<Key>;
@@ -47,8 +83,186 @@ pub struct Definition {
 }
 
 impl Definition {
+    /// The key of this definition's term under the default normalization.
     pub fn canonical_hash(&self) -> [u32; 5] {
-        canonical_hash(self.canonical_term.clone())
+        term_key(&self.canonical_term, &Normalization::default())
+    }
+
+    /// Checks for the fields a definition cannot be stored or found without.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.canonical_term.trim().is_empty() {
+            return Err("Empty entry.".to_string());
+        }
+        if self.term.trim().is_empty() {
+            return Err("Empty word.".to_string());
+        }
+        if self.definition.trim().is_empty() {
+            return Err("Empty definition.".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn score(&self) -> isize {
+        (self.thumbs_up as isize) - (self.thumbs_down as isize)
+    }
+}
+
+impl Document for Definition {
+    fn term(&self) -> &str {
+        &self.term
+    }
+
+    fn text(&self) -> Vec<&str> {
+        vec![self.definition.as_str(), self.example.as_str()]
+    }
+}
+
+impl Indexed<Key> for Definition {
+    fn secondary_keys(&self) -> Vec<Key> {
+        vec![author_key(&self.author), urban_id_key(self.urban_id)]
+    }
+}
+
+/// The secondary key under which a definition's term is referenced by its `urban_id`.
+pub fn urban_id_key(urban_id: u64) -> Key {
+    canonical_hash(format!("defid:{}", urban_id))
+}
+
+/// Every definition stored under one term, keyed by `urban_id`. Writes to a term merge
+/// into its collection rather than replacing it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Definitions {
+    pub by_urban_id: BTreeMap<u64, Definition>,
+}
+
+impl Definitions {
+    pub fn new() -> Definitions {
+        Definitions { by_urban_id: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, definition: Definition) -> Option<Definition> {
+        self.by_urban_id.insert(definition.urban_id, definition)
+    }
+
+    pub fn get(&self, urban_id: u64) -> Option<&Definition> {
+        self.by_urban_id.get(&urban_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_urban_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_urban_id.is_empty()
+    }
+
+    /// The `n` highest-scoring definitions, best first.
+    pub fn top(&self, n: usize) -> Vec<Definition> {
+        let mut definitions = self.by_urban_id.values().cloned().collect::<Vec<_>>();
+        definitions.sort_by(|a, b| {
+                                b.score()
+                                    .cmp(&a.score())
+                                    .then_with(|| a.urban_id.cmp(&b.urban_id))
+                            });
+        definitions.truncate(n);
+        definitions
+    }
+
+    pub fn best(&self) -> Option<Definition> {
+        self.top(1).pop()
+    }
+}
+
+impl From<Definition> for Definitions {
+    fn from(definition: Definition) -> Definitions {
+        let mut definitions = Definitions::new();
+        definitions.insert(definition);
+        definitions
+    }
+}
+
+impl Merge for Definitions {
+    fn merge(&mut self, other: Definitions) {
+        self.by_urban_id.extend(other.by_urban_id);
+    }
+}
+
+impl Document for Definitions {
+    fn term(&self) -> &str {
+        self.by_urban_id
+            .values()
+            .next()
+            .map_or("", |definition| definition.term())
+    }
+
+    fn text(&self) -> Vec<&str> {
+        self.by_urban_id
+            .values()
+            .flat_map(|definition| definition.text())
+            .collect()
+    }
+}
+
+impl Indexed<Key> for Definitions {
+    fn secondary_keys(&self) -> Vec<Key> {
+        let mut keys = self.by_urban_id
+            .values()
+            .flat_map(|definition| definition.secondary_keys())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+/// The secondary key under which definitions by `author` are referenced.
+pub fn author_key(author: &str) -> Key {
+    canonical_hash(format!("author:{}", author))
+}
+
+/// Server-side modifications of one stored `Definition`, applied atomically on the
+/// owning node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DefinitionUpdate {
+    IncrementThumbsUp { urban_id: u64, by: u64 },
+    IncrementThumbsDown { urban_id: u64, by: u64 },
+    /// Store the given definition unless one with the same `urban_id` scores at least
+    /// as high.
+    Merge(Definition),
+}
+
+impl DefinitionUpdate {
+    pub fn urban_id(&self) -> u64 {
+        match *self {
+            DefinitionUpdate::IncrementThumbsUp { urban_id, .. } |
+            DefinitionUpdate::IncrementThumbsDown { urban_id, .. } => urban_id,
+            DefinitionUpdate::Merge(ref definition) => definition.urban_id,
+        }
+    }
+}
+
+impl Update<Definitions> for DefinitionUpdate {
+    fn apply(&self, definitions: &mut Definitions) {
+        match *self {
+            DefinitionUpdate::IncrementThumbsUp { urban_id, by } => {
+                if let Some(definition) = definitions.by_urban_id.get_mut(&urban_id) {
+                    definition.thumbs_up = definition.thumbs_up.saturating_add(by);
+                }
+            }
+            DefinitionUpdate::IncrementThumbsDown { urban_id, by } => {
+                if let Some(definition) = definitions.by_urban_id.get_mut(&urban_id) {
+                    definition.thumbs_down = definition.thumbs_down.saturating_add(by);
+                }
+            }
+            DefinitionUpdate::Merge(ref other) => {
+                let better = definitions
+                    .get(other.urban_id)
+                    .map_or(true, |existing| other.score() > existing.score());
+                if better {
+                    definitions.insert(other.clone());
+                }
+            }
+        }
     }
 }
 
@@ -73,28 +287,6 @@ impl From<Definition> for (Key, Definition) {
     }
 }
 
-pub fn definitions_from_stdin() -> HashMap<Key, Definition> {
-    let mut rdr = csv::Reader::from_reader(io::stdin());
-    let des: csv::DeserializeRecordsIter<_, Definition> = rdr.deserialize();
-
-    let mut definitions: HashMap<Key, Definition> = HashMap::new();
-    for result in des {
-        if let Ok(definition) = result {
-            let (key, definition) = definition.into();
-            if definitions.contains_key(&key) {
-                let existing_score = (definitions[&key].thumbs_up as isize) -
-                                     (definitions[&key].thumbs_down as isize);
-                let new_score = (definition.thumbs_up as isize) - (definition.thumbs_down as isize);
-                if new_score <= existing_score {
-                    continue;
-                }
-            }
-            definitions.insert(key, definition);
-        }
-    }
-    definitions
-}
-
 // fn node_client(pred_id: Option<Key>) -> SyncClient {
 //     let (tx, rx) = mpsc::channel();
 //     thread::spawn(move || {
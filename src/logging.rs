@@ -0,0 +1,92 @@
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use slog::{Discard, Drain, Level, Logger, OwnedKVList, Record};
+use slog_async;
+use slog_json;
+use slog_term;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines on stderr.
+    Term,
+    /// One JSON object per line on stderr.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<LogFormat, ()> {
+        match s {
+            "term" => Ok(LogFormat::Term),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The least severe level logged, shared between a logger and whoever may change it
+/// while the node runs.
+#[derive(Clone, Debug)]
+pub struct LogLevel(Arc<AtomicUsize>);
+
+impl LogLevel {
+    pub fn new(level: Level) -> LogLevel {
+        LogLevel(Arc::new(AtomicUsize::new(level.as_usize())))
+    }
+
+    pub fn get(&self) -> Level {
+        Level::from_usize(self.0.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+    }
+
+    pub fn set(&self, level: Level) {
+        self.0.store(level.as_usize(), Ordering::Relaxed);
+    }
+}
+
+struct LevelFilter<D> {
+    drain: D,
+    level: LogLevel,
+}
+
+impl<D> Drain for LevelFilter<D>
+    where D: Drain
+{
+    type Ok = ();
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), D::Err> {
+        if record.level().is_at_least(self.level.get()) {
+            self.drain.log(record, values)?;
+        }
+        Ok(())
+    }
+}
+
+/// A logger writing to stderr in `format`, dropping records less severe than `level`.
+pub fn root_logger(format: LogFormat, level: &LogLevel) -> Logger {
+    let drain = match format {
+        LogFormat::Term => {
+            let decorator = slog_term::TermDecorator::new().build();
+            let drain = slog_term::FullFormat::new(decorator).build().fuse();
+            slog_async::Async::new(drain).build().fuse()
+        }
+        LogFormat::Json => {
+            let drain = slog_json::Json::default(io::stderr()).fuse();
+            slog_async::Async::new(drain).build().fuse()
+        }
+    };
+    let drain = LevelFilter {
+        drain: drain,
+        level: level.clone(),
+    };
+    Logger::root(drain.fuse(), o!())
+}
+
+/// A logger that drops everything, for when none is given.
+pub fn discard_logger() -> Logger {
+    Logger::root(Discard, o!())
+}
@@ -1,92 +1,423 @@
-#![feature(inclusive_range_syntax)]
 #![feature(plugin)]
 #![plugin(tarpc_plugins)]
 #![feature(slice_patterns)]
 
-extern crate csv;
-extern crate sha1;
-extern crate rand;
 extern crate tarpc;
 extern crate chord;
+extern crate serde_json;
+#[macro_use]
+extern crate slog;
 
-use std::time::Duration;
+use std::env;
+use std::io;
+use std::process;
+use std::fmt::Debug;
 use std::collections::HashMap;
-use std::sync::mpsc as stdmpsc;
-use std::thread;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use tarpc::future::{client, server};
 use tarpc::future::client::ClientExt;
 use tarpc::futures::Future;
 use tarpc::tokio_core::reactor;
-use rand::{Rng, StdRng};
+use slog::Level;
 use chord::*;
 
-fn node(i: usize) -> (Id, FutureClient) {
-    let (tx, rx) = stdmpsc::channel();
-    thread::spawn(move || {
-        let mut reactor = reactor::Core::new().unwrap();
-        let addr: SocketAddr = format!("0.0.0.0:{:?}", 4646 + i).parse().unwrap();
-        let node_id = Id::from(addr);
-        let node = Node::new(node_id);
-        let query_server = QueryEngine::new(node);
-        let chord_server = ChordServer::new(query_server);
-        let (_, server) = chord_server
-            .listen(addr, &reactor.handle(), server::Options::default())
-            .unwrap();
-        reactor
-            .handle()
-            .spawn(server
-                       .map(|v| {
-                                println!("server ended ok: {:?}", v);
-                            })
-                       .map_err(|e| {
-                                    println!("server ended err: {:?}", e);
-                                }));
-        tx.send(node_id).unwrap();
-        loop {
-            reactor.turn(None)
+const USAGE: &'static str = "Usage:
+    chord start [--config <file>] [<listen-addr> [<seed>...]]
+    chord get <node> <term>
+    chord set <node> <definition-json>
+    chord delete <node> <term>
+    chord exists <node> <term>
+    chord meta <node>
+    chord ring <node> [table|json|dot]
+    chord check <node>
+    chord health <node>
+    chord drain <node>
+    chord gateway <listen-addr> <node>
+    chord log-level <node> <level>
+    chord import <node> [csv|ndjson|json]   (reads stdin)
+    chord export <node> [ndjson|json]       (writes stdout)
+
+Any command may be preceded by --normalization <steps>, how terms are turned into keys:
+nfkc, case_fold and trim separated by commas, or none. It defaults to CHORD_NORMALIZATION,
+or for start to the config, and otherwise to all three.";
+
+const BATCH_SIZE: usize = 100;
+const CONCURRENCY: usize = 32;
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>();
+    let (steps, args) = match args.as_slice() {
+        &["--normalization", steps, ref rest..] => (Some(steps), rest),
+        rest => (None, rest),
+    };
+    let result = normalization(steps).and_then(|normalization| {
+        let normalization = &normalization;
+        match args {
+            &["start", ref rest..] => start(rest, steps),
+            &["get", node, term] => get(node, term, normalization),
+            &["set", node, json] => set(node, json, normalization),
+            &["delete", node, term] => delete(node, term, normalization),
+            &["exists", node, term] => exists(node, term, normalization),
+            &["meta", node] => meta(node),
+            &["ring", node] => ring(node, "table"),
+            &["ring", node, format] => ring(node, format),
+            &["check", node] => check(node),
+            &["health", node] => health(node),
+            &["drain", node] => drain(node),
+            &["gateway", listen, node] => gateway(listen, node, normalization),
+            &["log-level", node, level] => log_level(node, level),
+            &["import", node] => import(node, "csv", normalization),
+            &["import", node, format] => import(node, format, normalization),
+            &["export", node] => export(node, "ndjson"),
+            &["export", node, format] => export(node, format),
+            _ => Err(USAGE.to_string()),
         }
     });
-    let node_id = rx.recv().unwrap();
-    (node_id,
-     FutureClient::connect(node_id.addr, client::Options::default())
-         .wait()
-         .unwrap())
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
 
-fn main() {
-    let mut rng = StdRng::new().unwrap();
+fn parse_addr(addr: &str) -> Result<SocketAddr, String> {
+    addr.parse()
+        .map_err(|_| format!("Invalid address {:?}, expected ip:port.", addr))
+}
 
-    let mut node_clients = HashMap::new();
+fn request_failed<E: Debug>(e: E) -> String {
+    format!("Request failed: {:?}", e)
+}
 
-    let base_node = node(0);
-    println!("base node {:?}", base_node.1.meta().wait().unwrap());
-    node_clients.insert(base_node.0, base_node.1.clone());
-    println!();
+fn connect(addr: SocketAddr) -> Result<FutureClient, String> {
+    FutureClient::connect(addr, client::Options::default())
+        .wait()
+        .map_err(|e| format!("Could not connect to {}: {}", addr, e))
+}
 
-    let number_of_nodes = 8;
-    for i in 1..number_of_nodes {
-        let (new_node_id, new_node) = node(i);
-        println!("new node {:?}\n    {:?}",
-                 new_node_id.addr,
-                 new_node.meta().wait().unwrap());
+/// The normalization given by `--normalization`, else by `CHORD_NORMALIZATION`, else the
+/// default.
+fn normalization(steps: Option<&str>) -> Result<Normalization, String> {
+    let steps = steps
+        .map(|steps| steps.to_string())
+        .or_else(|| env::var("CHORD_NORMALIZATION").ok());
+    match steps {
+        Some(steps) => steps.parse().map_err(|e| format!("Invalid normalization: {}", e)),
+        None => Ok(Normalization::default()),
+    }
+}
 
-        let existing_node_ids = node_clients.keys().cloned().collect::<Vec<_>>();
-        let existing_node_id = rng.choose(&existing_node_ids).cloned().unwrap();
-        println!("trying to join to {:?}", existing_node_id.addr);
-        new_node.join(existing_node_id).wait().unwrap();
-        println!("new node {:?} after join\n    {:?}",
-                 new_node_id.addr,
-                 new_node.meta().wait().unwrap());
+fn json_format(format: &str) -> Result<JsonFormat, String> {
+    match format {
+        "ndjson" => Ok(JsonFormat::Ndjson),
+        "json" => Ok(JsonFormat::Array),
+        _ => Err(format!("Unknown format {:?}.\n{}", format, USAGE)),
+    }
+}
 
-        node_clients.insert(new_node_id, new_node);
-        println!();
+/// Run a node until it stops. Settings come from the file given by `--config` or
+/// `CHORD_CONFIG`, overridden by `CHORD_*` environment variables and then by any listen
+/// address and seeds given here, and by the normalization `steps`. Without seeds the node
+/// starts a ring of its own.
+fn start(args: &[&str], steps: Option<&str>) -> Result<(), String> {
+    let (path, args) = match args {
+        &["--config", path, ref rest..] => (Some(PathBuf::from(path)), rest),
+        _ => (env::var_os(CONFIG_PATH_VAR).map(PathBuf::from), args),
+    };
+    let mut config = Config::load(path.as_ref().map(|path| path.as_path()))
+        .map_err(|e| format!("Invalid config: {:?}", e))?;
+    match args {
+        &[] => {}
+        &[listen] => config.listen = parse_addr(listen)?,
+        &[listen, ref seeds..] => {
+            config.listen = parse_addr(listen)?;
+            config.bootstrap = seeds.iter().map(|seed| seed.to_string()).collect();
+        }
     }
+    if let Some(steps) = steps {
+        config.normalization = normalization(Some(steps))?;
+    }
+    let addrs = config.addrs();
+    let log_level = LogLevel::new(config.log_level());
+    let log = root_logger(config.log_format, &log_level);
+
+    let mut reactor = reactor::Core::new().map_err(|e| e.to_string())?;
 
-    println!("done");
+    let mut node = Node::new(addrs.id());
+    node.tombstone_grace = config.tombstone_grace();
+    let query_server = QueryEngine::new(node);
+    let chord_server = ChordServer::new(query_server)
+        .request_timeout(config.request_timeout())
+        .logger(log.clone(), log_level);
+    reactor
+        .handle()
+        .spawn(chord_server.sweeper(config.sweep_interval()));
 
-    let sleep_intervals = Duration::from_secs(3);
-    loop {
-        thread::sleep(sleep_intervals);
+    let (server_handle, server) = chord_server
+        .clone()
+        .listen(addrs.bind, &reactor.handle(), server::Options::default())
+        .map_err(|e| format!("Could not listen on {}: {}", addrs.bind, e))?;
+    let bound = addrs.bound_to(server_handle.addr());
+    if bound != addrs {
+        // The port is only known now when listening on port 0.
+        chord_server.rename(bound.id()).wait().unwrap();
     }
+    let addrs = bound;
+    info!(log, "Listening";
+          "bind" => addrs.bind.to_string(),
+          "advertise" => addrs.advertise.to_string());
+    if !addrs.is_routable() {
+        warn!(log, "Other hosts cannot reach the advertised address, set advertise";
+              "advertise" => addrs.advertise.to_string());
+    }
+
+    if let Some(http_addr) = config.http_listen {
+        // The gateway goes through the node's own RPC server, like any other client.
+        let local = connect(server_handle.addr())?;
+        let service = HttpService::new(chord_server.clone())
+            .gateway(local)
+            .normalization(config.normalization);
+        let http_server = serve_http(&http_addr, &reactor.handle(), service, log.clone())
+                .map_err(|e| format!("Could not serve HTTP on {}: {}", http_addr, e))?;
+        reactor.handle().spawn(http_server);
+        info!(log, "Serving HTTP"; "addr" => http_addr.to_string());
+    }
+
+    if !config.bootstrap.is_empty() {
+        chord_server.joining();
+        let seeds = config.bootstrap.clone();
+        let retries = config.join_retries;
+        let backoff = config.join_backoff();
+        let own_id = addrs.id();
+        let (join_log, error_log) = (log.clone(), log.clone());
+        let joiner = FutureClient::connect(server_handle.addr(), client::Options::default())
+            .map_err(|e| format!("Could not connect to local node: {:?}", e))
+            .and_then(move |c| join_seeds(c, own_id, seeds, retries, backoff, join_log))
+            .map_err(move |e| {
+                         error!(error_log, "Not joined"; "error" => e);
+                     });
+        reactor.handle().spawn(joiner.map(|_| ()));
+    }
+
+    reactor
+        .run(server)
+        .map_err(|e| format!("Stopped listening: {:?}", e))
+}
+
+fn get(node: &str, term: &str, normalization: &Normalization) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    match client
+              .get_by_term(term, normalization)
+              .wait()
+              .map_err(request_failed)? {
+        Some(definition) => {
+            let json = serde_json::to_string_pretty(&definition).map_err(|e| e.to_string())?;
+            println!("{}", json);
+            Ok(())
+        }
+        None => Err(format!("No definition of {:?}.", term)),
+    }
+}
+
+/// Store a definition given as a JSON object in the same shape `export` writes.
+fn set(node: &str, json: &str, normalization: &Normalization) -> Result<(), String> {
+    let definition = serde_json::from_str::<Definition>(json)
+        .map_err(|e| format!("Invalid definition: {}", e))?;
+    definition.validate()?;
+    let key = term_key(&definition.canonical_term, normalization);
+    let client = connect(parse_addr(node)?)?;
+    client
+        .set(key, definition)
+        .wait()
+        .map_err(request_failed)
+}
+
+fn delete(node: &str, term: &str, normalization: &Normalization) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    let key = term_key(term, normalization);
+    if client.delete(key).wait().map_err(request_failed)? {
+        println!("Deleted {:?}.", term);
+        Ok(())
+    } else {
+        Err(format!("No definition of {:?}.", term))
+    }
+}
+
+fn exists(node: &str, term: &str, normalization: &Normalization) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    let key = term_key(term, normalization);
+    println!("{}", client.exists(key).wait().map_err(request_failed)?);
+    Ok(())
+}
+
+fn meta(node: &str) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    println!("{:?}", client.meta().wait().map_err(request_failed)?);
+    Ok(())
+}
+
+/// Print every node in the ring, starting from `node` and following successors. Nodes
+/// that do not answer are listed after.
+fn ring(node: &str, format: &str) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    let topology = client.topology().wait().map_err(request_failed)?;
+    let metas = &topology.nodes;
+    match format {
+        "table" => print!("{}", topology_table(metas)),
+        "json" => {
+            let json = serde_json::to_string_pretty(&topology).map_err(|e| e.to_string())?;
+            println!("{}", json);
+        }
+        "dot" => print!("{}", topology_dot(metas)),
+        _ => return Err(format!("Unknown format {:?}.\n{}", format, USAGE)),
+    }
+    for id in &topology.unreachable {
+        eprintln!("{} did not answer.", id.addr);
+    }
+
+    let closed = match (metas.first(), metas.last()) {
+        (Some(first), Some(last)) => {
+            last.relations
+                .map_or(metas.len() == 1, |relations| relations.successor_id == first.id)
+        }
+        _ => false,
+    };
+    if !closed {
+        eprintln!("Ring does not lead back to {} within {} nodes.",
+                  node,
+                  MAX_RING_HOPS);
+    }
+    eprintln!("{} nodes, {} items.",
+              metas.len(),
+              metas.iter().map(|meta| meta.itemcount).sum::<usize>());
+    Ok(())
+}
+
+/// Walk the ring from `node` and report every broken invariant. Fails if there are any.
+fn check(node: &str) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    let topology = client.topology().wait().map_err(request_failed)?;
+    let mut misplaced = HashMap::new();
+    for meta in &topology.nodes {
+        let keys = connect(meta.id.addr)
+            .and_then(|c| c.misplaced_keys().wait().map_err(request_failed));
+        match keys {
+            Ok(keys) => {
+                misplaced.insert(meta.id, keys);
+            }
+            Err(e) => eprintln!("Skipping placement check of {}: {}", meta.id.addr, e),
+        }
+    }
+
+    let violations = check_ring(&topology, &misplaced);
+    for violation in &violations {
+        println!("{}", violation.describe());
+    }
+    if violations.is_empty() {
+        println!("{} nodes, no violations.", topology.nodes.len());
+        Ok(())
+    } else {
+        Err(format!("{} violations.", violations.len()))
+    }
+}
+
+/// Print what `node` reports about its health, failing unless it is ready, for use as a
+/// readiness probe.
+fn health(node: &str) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    let health = client.health().wait().map_err(request_failed)?;
+    let json = serde_json::to_string_pretty(&health).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    if health.is_ready() {
+        Ok(())
+    } else {
+        Err(format!("{} is not ready.", node))
+    }
+}
+
+/// Mark `node` as leaving, so that it reports itself unready while still answering until
+/// it is stopped. This cannot be undone short of restarting the node.
+fn drain(node: &str) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    client.drain().wait().map_err(request_failed)?;
+    eprintln!("Draining {}.", node);
+    Ok(())
+}
+
+/// Serve the HTTP gateway on `listen` until it stops, passing requests on to the ring
+/// through `node`.
+fn gateway(listen: &str, node: &str, normalization: &Normalization) -> Result<(), String> {
+    let listen = parse_addr(listen)?;
+    let client = connect(parse_addr(node)?)?;
+    let log = root_logger(LogFormat::Term, &LogLevel::new(Level::Info));
+    let mut reactor = reactor::Core::new().map_err(|e| e.to_string())?;
+    let service = HttpService::proxy(client).normalization(*normalization);
+    let http_server = serve_http(&listen, &reactor.handle(), service, log.clone())
+        .map_err(|e| format!("Could not serve HTTP on {}: {}", listen, e))?;
+    info!(log, "Serving HTTP gateway"; "addr" => listen.to_string(), "node" => node);
+    reactor
+        .run(http_server)
+        .map_err(|_| "Stopped serving HTTP.".to_string())
+}
+
+/// Change the least severe level `node` logs, until it restarts.
+fn log_level(node: &str, level: &str) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    client
+        .set_log_level(level.to_string())
+        .wait()
+        .map(|_| ())
+        .map_err(|_| format!("Unknown log level {:?}.", level))
+}
+
+fn import(node: &str, format: &str, normalization: &Normalization) -> Result<(), String> {
+    let client = connect(parse_addr(node)?)?;
+    let mut reactor = reactor::Core::new().map_err(|e| e.to_string())?;
+    let importer = match format {
+        "csv" => {
+            import_csv(io::stdin(),
+                       client,
+                       *normalization,
+                       BATCH_SIZE,
+                       CONCURRENCY)
+        }
+        _ => {
+            import_json(io::BufReader::new(io::stdin()),
+                        json_format(format)?,
+                        client,
+                        *normalization,
+                        BATCH_SIZE,
+                        CONCURRENCY)
+        }
+    };
+    let report = reactor
+        .run(importer)
+        .map_err(|_| "Import failed.".to_string())?;
+
+    for rejection in &report.rejected {
+        eprintln!("Rejected line {}: {}", rejection.line, rejection.reason);
+    }
+    for rejection in &report.unstored {
+        eprintln!("Could not store line {}: {}", rejection.line, rejection.reason);
+    }
+    eprintln!("Imported {} definitions, rejected {}, could not store {}.",
+              report.imported,
+              report.rejected.len(),
+              report.unstored.len());
+    if report.unstored.is_empty() {
+        Ok(())
+    } else {
+        Err("Some definitions were not stored.".to_string())
+    }
+}
+
+fn export(node: &str, format: &str) -> Result<(), String> {
+    let format = json_format(format)?;
+    let client = connect(parse_addr(node)?)?;
+    let mut reactor = reactor::Core::new().map_err(|e| e.to_string())?;
+    let export = export_json(client, io::stdout(), format, BATCH_SIZE);
+    let (_, count) = reactor.run(export).map_err(|e| format!("Export failed: {:?}", e))?;
+    eprintln!("Exported {} definitions.", count);
+    Ok(())
 }
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the RPC latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 10] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+                                        10.0];
+
+/// How an RPC ended, as far as metrics are concerned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Error,
+    TimedOut,
+}
+
+#[derive(Clone, Debug, Default)]
+struct RpcStats {
+    calls: u64,
+    errors: u64,
+    timeouts: u64,
+    forwards: u64,
+    latency_buckets: [u64; 10],
+    latency_sum: f64,
+}
+
+/// Counters and latency histograms per RPC, shared by every clone.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    rpcs: Arc<Mutex<BTreeMap<&'static str, RpcStats>>>,
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn write_counter(text: &mut String,
+                 name: &str,
+                 help: &str,
+                 rpcs: &BTreeMap<&'static str, RpcStats>,
+                 value: fn(&RpcStats) -> u64) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} counter", name).unwrap();
+    for (rpc, stats) in rpcs {
+        writeln!(text, "{}{{rpc=\"{}\"}} {}", name, rpc, value(stats)).unwrap();
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Record a call of `rpc` that was passed on to another node, i.e. one hop.
+    pub fn forwarded(&self, rpc: &'static str) {
+        let mut rpcs = self.rpcs.lock().unwrap();
+        rpcs.entry(rpc).or_insert_with(RpcStats::default).forwards += 1;
+    }
+
+    /// Record a finished call of `rpc`.
+    pub fn observe(&self, rpc: &'static str, latency: Duration, outcome: Outcome) {
+        let mut rpcs = self.rpcs.lock().unwrap();
+        let stats = rpcs.entry(rpc).or_insert_with(RpcStats::default);
+        stats.calls += 1;
+        match outcome {
+            Outcome::Ok => {}
+            Outcome::Error => stats.errors += 1,
+            Outcome::TimedOut => stats.timeouts += 1,
+        }
+        let latency = seconds(latency);
+        stats.latency_sum += latency;
+        for (bucket, bound) in stats.latency_buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if latency <= *bound {
+                *bucket += 1;
+            }
+        }
+    }
+
+    /// Everything recorded so far, followed by `gauges` given as (name, help, value), in
+    /// the Prometheus text exposition format.
+    pub fn render(&self, gauges: &[(&str, &str, f64)]) -> String {
+        let rpcs = self.rpcs.lock().unwrap();
+        let mut text = String::new();
+        write_counter(&mut text,
+                      "chord_rpc_calls_total",
+                      "RPCs handled.",
+                      &rpcs,
+                      |stats| stats.calls);
+        write_counter(&mut text,
+                      "chord_rpc_errors_total",
+                      "RPCs that failed other than by timing out.",
+                      &rpcs,
+                      |stats| stats.errors);
+        write_counter(&mut text,
+                      "chord_rpc_timeouts_total",
+                      "RPCs that timed out waiting on another node.",
+                      &rpcs,
+                      |stats| stats.timeouts);
+        write_counter(&mut text,
+                      "chord_rpc_forwards_total",
+                      "RPCs passed on to another node, one hop each.",
+                      &rpcs,
+                      |stats| stats.forwards);
+
+        let name = "chord_rpc_latency_seconds";
+        writeln!(text, "# HELP {} Time taken to answer RPCs.", name).unwrap();
+        writeln!(text, "# TYPE {} histogram", name).unwrap();
+        for (rpc, stats) in rpcs.iter() {
+            for (count, bound) in stats.latency_buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+                writeln!(text,
+                         "{}_bucket{{rpc=\"{}\",le=\"{}\"}} {}",
+                         name,
+                         rpc,
+                         bound,
+                         count)
+                        .unwrap();
+            }
+            writeln!(text,
+                     "{}_bucket{{rpc=\"{}\",le=\"+Inf\"}} {}",
+                     name,
+                     rpc,
+                     stats.calls)
+                    .unwrap();
+            writeln!(text, "{}_sum{{rpc=\"{}\"}} {}", name, rpc, stats.latency_sum).unwrap();
+            writeln!(text, "{}_count{{rpc=\"{}\"}} {}", name, rpc, stats.calls).unwrap();
+        }
+
+        for &(name, help, value) in gauges {
+            writeln!(text, "# HELP {} {}", name, help).unwrap();
+            writeln!(text, "# TYPE {} gauge", name).unwrap();
+            writeln!(text, "{} {}", name, value).unwrap();
+        }
+        text
+    }
+}
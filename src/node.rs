@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tarpc::serde::Serialize;
 use tarpc::serde::de::DeserializeOwned;
 use super::*;
@@ -13,13 +15,133 @@ pub trait NodeId: Copy + Debug + Send + Serialize {
 
 pub type NodeResult<T, I> = Result<T, I>;
 
+/// Values that absorb later writes to the same key instead of being replaced by them.
+pub trait Merge {
+    fn merge(&mut self, other: Self);
+}
+
+/// Values that can also be found through secondary keys, such as a hash of their author.
+/// The node owning a secondary key keeps references back to the primary keys.
+pub trait Indexed<K> {
+    fn secondary_keys(&self) -> Vec<K>;
+}
+
+/// Milliseconds since the Unix epoch.
+pub type Timestamp = u64;
+
+/// How long a tombstone is kept before it may be garbage-collected. It must outlive
+/// any stale copy of the key that could otherwise be merged back in.
+pub const DEFAULT_TOMBSTONE_GRACE_SECS: u64 = 24 * 60 * 60;
+
+pub fn timestamp() -> Timestamp {
+    millis(SystemTime::now()
+               .duration_since(UNIX_EPOCH)
+               .expect("System clock is before the Unix epoch."))
+}
+
+/// `duration` in milliseconds, or the most there can be for durations too long for that.
+fn millis(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_mul(1000)
+        .saturating_add((duration.subsec_nanos() / 1_000_000) as u64)
+}
+
+/// A stored value along with when it was written. Deletes leave a tombstone (an entry
+/// without a value) so that older copies of the key cannot resurrect it.
+///
+/// Expiry is an absolute time, so it is unaffected by the entry moving between nodes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry<T> {
+    pub value: Option<T>,
+    pub timestamp: Timestamp,
+    pub expires_at: Option<Timestamp>,
+}
+
+impl<T> Entry<T> {
+    pub fn new(value: T) -> Entry<T> {
+        Entry {
+            value: Some(value),
+            timestamp: timestamp(),
+            expires_at: None,
+        }
+    }
+
+    pub fn expiring(value: T, ttl: Duration) -> Entry<T> {
+        let now = timestamp();
+        Entry {
+            value: Some(value),
+            timestamp: now,
+            expires_at: Some(now.saturating_add(millis(ttl))),
+        }
+    }
+
+    pub fn tombstone() -> Entry<T> {
+        Entry {
+            value: None,
+            timestamp: timestamp(),
+            expires_at: None,
+        }
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.value.is_none()
+    }
+
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+
+    /// The value, unless this is a tombstone or has expired.
+    pub fn live_value(&self, now: Timestamp) -> Option<&T> {
+        if self.is_expired(now) {
+            None
+        } else {
+            self.value.as_ref()
+        }
+    }
+
+    /// Whether this entry should replace `other`. Newer writes win, and a tombstone
+    /// wins a tie.
+    pub fn supersedes(&self, other: &Entry<T>) -> bool {
+        self.timestamp > other.timestamp ||
+        (self.timestamp == other.timestamp && self.is_tombstone())
+    }
+}
+
+/// An in-place modification applied to a stored value on its owning node, so that
+/// read-modify-write cycles happen under a single lock rather than across the network.
+pub trait Update<T> {
+    fn apply(&self, value: &mut T);
+}
+
+/// A reference from a secondary key to a primary key, waiting to be sent to the node
+/// owning the secondary key. A tombstone entry removes the reference.
+#[derive(Clone, Debug)]
+pub struct ReferenceChange<K> {
+    pub index_key: K,
+    pub primary_key: K,
+    pub entry: Entry<()>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Node<I, T>
     where I: NodeId,
           T: Clone + Debug + Send
 {
     pub meta: NodeMeta<I>,
-    pub items: HashMap<I::Key, T>,
+    pub items: HashMap<I::Key, Entry<T>>,
+    pub tombstone_grace: Duration,
+    pub search_index: SearchIndex<I::Key>,
+    pub references: HashMap<I::Key, HashMap<I::Key, Entry<()>>>,
+    pub reference_changes: Vec<ReferenceChange<I::Key>>,
+    /// The predecessor and the successor each replaced last. A node retrying a join
+    /// that went through, but whose reply was lost, is answered the same way again.
+    pub previous_predecessor_id: Option<I>,
+    pub previous_successor_id: Option<I>,
+    /// What was handed over to the last predecessor to join, kept to answer a retry.
+    pub handed_over_items: HashMap<I::Key, Entry<T>>,
+    pub handed_over_references: HashMap<I::Key, HashMap<I::Key, Entry<()>>>,
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
@@ -60,6 +182,22 @@ impl<I> NodeMeta<I>
         }
     }
 
+    /// Whether this node owns the keys immediately following `key`, i.e. where a scan
+    /// resuming after `key` should continue.
+    pub fn owns_after(&self, key: I::Key) -> bool {
+        if self.relations.is_none() {
+            return true;
+        }
+
+        let relations = self.relations.unwrap();
+        let predecessor_key = relations.predecessor_id.key();
+        if predecessor_key > self.id.key() {
+            key >= predecessor_key || key < self.id.key()
+        } else {
+            key >= predecessor_key && key < self.id.key()
+        }
+    }
+
     pub fn next(&self, _: I::Key) -> I {
         let relations = self.relations.expect("No relations set.");
         relations.successor_id
@@ -68,7 +206,7 @@ impl<I> NodeMeta<I>
 
 impl<I, T> Node<I, T>
     where I: NodeId,
-          T: Clone + Debug + Send
+          T: Clone + Debug + Send + Merge + Document + Indexed<I::Key>
 {
     pub fn new(id: I) -> Node<I, T> {
         Node {
@@ -78,6 +216,29 @@ impl<I, T> Node<I, T>
                 itemcount: 0,
             },
             items: HashMap::new(),
+            tombstone_grace: Duration::from_secs(DEFAULT_TOMBSTONE_GRACE_SECS),
+            search_index: SearchIndex::new(),
+            references: HashMap::new(),
+            reference_changes: vec![],
+            previous_predecessor_id: None,
+            previous_successor_id: None,
+            handed_over_items: HashMap::new(),
+            handed_over_references: HashMap::new(),
+        }
+    }
+
+    /// Take `successor_id` as the successor, remembering the one it replaces. Fails
+    /// when the node is not part of a ring.
+    pub fn set_successor(&mut self, successor_id: I) -> bool {
+        match self.meta.relations.as_mut() {
+            Some(relations) => {
+                if relations.successor_id.key() != successor_id.key() {
+                    self.previous_successor_id = Some(relations.successor_id);
+                    relations.successor_id = successor_id;
+                }
+                true
+            }
+            None => false,
         }
     }
 
@@ -86,12 +247,242 @@ impl<I, T> Node<I, T>
                                        predecessor_id: precede_reply.predecessor_id,
                                        successor_id: precede_reply.successor_id,
                                    });
-        self.items = precede_reply.transfer_items;
+        for (key, entry) in precede_reply.transfer_items {
+            self.merge(key, entry);
+        }
+        for (index_key, references) in precede_reply.transfer_references {
+            for (primary_key, entry) in references {
+                self.merge_reference(index_key, primary_key, entry);
+            }
+        }
+    }
+
+    /// Remove the items and references the node no longer owns, after taking a new
+    /// predecessor, and keep them as what was handed over to it. Tombstones go too, so
+    /// that the new predecessor cannot take a stale copy of a deleted key for live.
+    pub fn hand_over(&mut self) {
+        let keys = self.items
+            .keys()
+            .cloned()
+            .filter(|key| !self.meta.owns(*key))
+            .collect::<Vec<_>>();
+        let mut items = HashMap::new();
+        for key in keys {
+            if let Some(entry) = self.items.remove(&key) {
+                self.search_index.remove(key);
+                if !entry.is_tombstone() {
+                    self.meta.itemcount -= 1;
+                }
+                items.insert(key, entry);
+            }
+        }
+        let index_keys = self.references
+            .keys()
+            .cloned()
+            .filter(|key| !self.meta.owns(*key))
+            .collect::<Vec<_>>();
+        let mut references = HashMap::new();
+        for index_key in index_keys {
+            if let Some(entries) = self.references.remove(&index_key) {
+                references.insert(index_key, entries);
+            }
+        }
+        self.handed_over_items = items;
+        self.handed_over_references = references;
+    }
+
+    /// Store `entry` unless the existing entry for `key` supersedes it. Used for entries
+    /// handed over from other nodes, whose references already exist.
+    pub fn merge(&mut self, key: I::Key, entry: Entry<T>) -> bool {
+        if let Some(existing) = self.items.get(&key) {
+            if !entry.supersedes(existing) {
+                return false;
+            }
+        }
+        self.store_entry(key, entry);
+        true
+    }
+
+    /// Store a local write, queueing the reference changes it implies.
+    fn store(&mut self, key: I::Key, entry: Entry<T>) -> bool {
+        let old_keys = self.items
+            .get(&key)
+            .and_then(|existing| existing.value.as_ref())
+            .map_or(vec![], |value| value.secondary_keys());
+        let new_keys = entry
+            .value
+            .as_ref()
+            .map_or(vec![], |value| value.secondary_keys());
+        self.record_reference_changes(key, &old_keys, &new_keys, entry.timestamp, entry.expires_at);
+        self.store_entry(key, entry)
+    }
+
+    /// Unconditionally store `entry`, keeping `itemcount` in line with live values.
+    /// Returns whether a live value was replaced.
+    fn store_entry(&mut self, key: I::Key, entry: Entry<T>) -> bool {
+        match entry.value {
+            Some(ref value) => self.search_index.insert(key, value),
+            None => self.search_index.remove(key),
+        }
+        let is_live = !entry.is_tombstone();
+        let was_live = self.items
+            .insert(key, entry)
+            .map_or(false, |existing| !existing.is_tombstone());
+        match (was_live, is_live) {
+            (false, true) => self.meta.itemcount += 1,
+            (true, false) => self.meta.itemcount -= 1,
+            _ => {}
+        }
+        was_live
+    }
+
+    /// Queue references from `key`'s new secondary keys and tombstones for those it no
+    /// longer has. References expire along with the value they point to.
+    fn record_reference_changes(&mut self,
+                                key: I::Key,
+                                old_keys: &[I::Key],
+                                new_keys: &[I::Key],
+                                timestamp: Timestamp,
+                                expires_at: Option<Timestamp>) {
+        for &index_key in old_keys {
+            if new_keys.contains(&index_key) {
+                continue;
+            }
+            self.reference_changes
+                .push(ReferenceChange {
+                          index_key: index_key,
+                          primary_key: key,
+                          entry: Entry {
+                              value: None,
+                              timestamp: timestamp,
+                              expires_at: None,
+                          },
+                      });
+        }
+        for &index_key in new_keys {
+            self.reference_changes
+                .push(ReferenceChange {
+                          index_key: index_key,
+                          primary_key: key,
+                          entry: Entry {
+                              value: Some(()),
+                              timestamp: timestamp,
+                              expires_at: expires_at,
+                          },
+                      });
+        }
+    }
+
+    pub fn take_reference_changes(&mut self) -> Vec<ReferenceChange<I::Key>> {
+        mem::replace(&mut self.reference_changes, vec![])
+    }
+
+    /// Queue `changes` again after they could not be sent. Their order does not matter,
+    /// as the owner of each secondary key keeps whichever entry is newest.
+    pub fn requeue_reference_changes(&mut self, changes: Vec<ReferenceChange<I::Key>>) {
+        self.reference_changes.extend(changes);
+    }
+
+    fn merge_reference(&mut self, index_key: I::Key, primary_key: I::Key, entry: Entry<()>) {
+        let references = self.references
+            .entry(index_key)
+            .or_insert_with(HashMap::new);
+        let supersedes = references
+            .get(&primary_key)
+            .map_or(true, |existing| entry.supersedes(existing));
+        if supersedes {
+            references.insert(primary_key, entry);
+        }
+    }
+
+    pub fn reference(&mut self,
+                     index_key: I::Key,
+                     primary_key: I::Key,
+                     entry: Entry<()>)
+                     -> NodeResult<(), I> {
+        if self.meta.owns(index_key) {
+            self.merge_reference(index_key, primary_key, entry);
+            Ok(())
+        } else {
+            Err(self.meta.next(index_key))
+        }
+    }
+
+    /// Primary keys currently referenced from `index_key`.
+    pub fn references(&self, index_key: I::Key) -> NodeResult<Vec<I::Key>, I> {
+        if self.meta.owns(index_key) {
+            let now = timestamp();
+            Ok(self.references
+                   .get(&index_key)
+                   .map_or(vec![], |references| {
+                references
+                    .iter()
+                    .filter(|&(_, entry)| entry.live_value(now).is_some())
+                    .map(|(&primary_key, _)| primary_key)
+                    .collect()
+            }))
+        } else {
+            Err(self.meta.next(index_key))
+        }
+    }
+
+    /// Drop tombstones that have outlived the grace period. Returns how many were removed.
+    pub fn collect_tombstones(&mut self) -> usize {
+        let cutoff = timestamp().saturating_sub(millis(self.tombstone_grace));
+        let before = self.items.len();
+        self.items
+            .retain(|_, entry| !entry.is_tombstone() || entry.timestamp > cutoff);
+        let mut removed = before - self.items.len();
+        for references in self.references.values_mut() {
+            let before = references.len();
+            references.retain(|_, entry| !entry.is_tombstone() || entry.timestamp > cutoff);
+            removed += before - references.len();
+        }
+        self.references.retain(|_, references| !references.is_empty());
+        removed
+    }
+
+    /// Drop values whose time-to-live has passed. Returns how many were removed.
+    pub fn collect_expired(&mut self) -> usize {
+        let now = timestamp();
+        let expired = self.items
+            .iter()
+            .filter(|&(_, entry)| !entry.is_tombstone() && entry.is_expired(now))
+            .map(|(&key, _)| key)
+            .collect::<Vec<_>>();
+        for key in &expired {
+            self.items.remove(key);
+            self.search_index.remove(*key);
+        }
+        self.meta.itemcount -= expired.len();
+        let mut removed = expired.len();
+        for references in self.references.values_mut() {
+            let before = references.len();
+            references.retain(|_, entry| entry.is_tombstone() || !entry.is_expired(now));
+            removed += before - references.len();
+        }
+        self.references.retain(|_, references| !references.is_empty());
+        removed
+    }
+
+    /// Keys of stored items, tombstones included, that this node does not own. Should
+    /// always be empty; anything here is missed by lookups.
+    pub fn misplaced_keys(&self) -> Vec<I::Key> {
+        let mut keys = self.items
+            .keys()
+            .cloned()
+            .filter(|key| !self.meta.owns(*key))
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
     }
 
     pub fn exists(&self, key: I::Key) -> NodeResult<bool, I> {
         if self.meta.owns(key) {
-            Ok(self.items.contains_key(&key))
+            let now = timestamp();
+            Ok(self.items
+                   .get(&key)
+                   .map_or(false, |entry| entry.live_value(now).is_some()))
         } else {
             Err(self.meta.next(key))
         }
@@ -99,31 +490,186 @@ impl<I, T> Node<I, T>
 
     pub fn get(&self, key: I::Key) -> NodeResult<Option<&T>, I> {
         if self.meta.owns(key) {
-            Ok(self.items.get(&key))
+            let now = timestamp();
+            Ok(self.items.get(&key).and_then(|entry| entry.live_value(now)))
         } else {
             Err(self.meta.next(key))
         }
     }
 
-    pub fn set(&mut self, key: I::Key, value: T) -> NodeResult<(), I> {
+    /// Merge `value` into any live value under `key`. The result takes on the new `ttl`.
+    pub fn set(&mut self, key: I::Key, value: T, ttl: Option<Duration>) -> NodeResult<(), I> {
         if self.meta.owns(key) {
-            if self.items.insert(key, value).is_none() {
-                self.meta.itemcount += 1;
-            }
+            let now = timestamp();
+            let value = match self.items.get(&key).and_then(|entry| entry.live_value(now)) {
+                Some(existing) => {
+                    let mut merged = existing.clone();
+                    merged.merge(value);
+                    merged
+                }
+                None => value,
+            };
+            let entry = match ttl {
+                Some(ttl) => Entry::expiring(value, ttl),
+                None => Entry::new(value),
+            };
+            self.store(key, entry);
             Ok(())
         } else {
             Err(self.meta.next(key))
         }
     }
 
+    pub fn update<U>(&mut self, key: I::Key, update: &U) -> NodeResult<Option<&T>, I>
+        where U: Update<T>
+    {
+        if !self.meta.owns(key) {
+            return Err(self.meta.next(key));
+        }
+
+        let now = timestamp();
+        let changed = match self.items.get_mut(&key) {
+            Some(entry) => {
+                if entry.is_expired(now) {
+                    None
+                } else {
+                    match entry.value {
+                        Some(ref mut value) => {
+                            let old_keys = value.secondary_keys();
+                            update.apply(value);
+                            self.search_index.insert(key, &*value);
+                            entry.timestamp = now;
+                            Some((old_keys, value.secondary_keys(), entry.expires_at))
+                        }
+                        None => None,
+                    }
+                }
+            }
+            None => None,
+        };
+        match changed {
+            Some((old_keys, new_keys, expires_at)) => {
+                self.record_reference_changes(key, &old_keys, &new_keys, now, expires_at);
+                Ok(self.items.get(&key).and_then(|entry| entry.value.as_ref()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Live items from `start` up to and including `end`, in key order, limited to this
+    /// node's range. The page says where the scan continues, if anywhere.
+    pub fn scan(&self,
+                start: ScanBound<I::Key>,
+                end: I::Key,
+                limit: usize)
+                -> NodeResult<ScanPage<I::Key, T>, I> {
+        let owns_start = match start {
+            ScanBound::Included(key) => self.meta.owns(key),
+            ScanBound::Excluded(key) => self.meta.owns_after(key),
+        };
+        if !owns_start {
+            return Err(self.meta.next(start.key()));
+        }
+
+        // A node whose range wraps around owns the bottom and the top of the keyspace. A
+        // scan starting in the bottom part only takes that part, and comes back for the
+        // top once the rest of the ring has been scanned.
+        let id_key = self.meta.id.key();
+        let below_wrap = self.meta
+            .relations
+            .map_or(false, |relations| {
+                relations.predecessor_id.key() > id_key && start.key() <= id_key
+            });
+        let now = timestamp();
+        let mut items = self.items
+            .iter()
+            .filter(|&(&key, _)| start.contains(key) && key <= end && self.meta.owns(key))
+            .filter(|&(&key, _)| !below_wrap || key <= id_key)
+            .filter_map(|(&key, entry)| entry.live_value(now).map(|value| (key, value.clone())))
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        items.truncate(limit);
+        let expiring = items
+            .iter()
+            .filter_map(|&(key, _)| self.items[&key].expires_at.map(|expires_at| (key, expires_at)))
+            .collect();
+
+        let next = if limit > 0 && items.len() == limit {
+            items.last().map(|&(key, _)| ScanBound::Excluded(key))
+        } else {
+            match self.meta.relations {
+                None => None,
+                Some(relations) => {
+                    // Past the top of the keyspace there is nothing more to scan, so a
+                    // node whose range wraps around only continues from its lower part.
+                    let predecessor_key = relations.predecessor_id.key();
+                    let wrapped = predecessor_key > id_key && start.key() >= predecessor_key;
+                    if wrapped || end <= id_key {
+                        None
+                    } else {
+                        Some(ScanBound::Excluded(id_key))
+                    }
+                }
+            }
+        };
+        Ok(ScanPage {
+               items,
+               expiring,
+               next,
+           })
+    }
+
+    /// The best `limit` live items owned by this node matching `query`.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit<I::Key, T>> {
+        let now = timestamp();
+        let hits = self.search_index
+            .search(query)
+            .into_iter()
+            .filter(|&(key, _)| self.meta.owns(key))
+            .filter_map(|(key, score)| {
+                self.items
+                    .get(&key)
+                    .and_then(|entry| entry.live_value(now))
+                    .map(|value| {
+                             SearchHit {
+                                 key: key,
+                                 score: score,
+                                 value: value.clone(),
+                             }
+                         })
+            })
+            .collect::<Vec<_>>();
+        merge_hits(hits, vec![], limit)
+    }
+
+    /// Up to `limit` distinct terms of live items owned by this node that start with
+    /// `prefix`, in order.
+    pub fn autocomplete(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let now = timestamp();
+        let live_value = |key: I::Key| {
+            self.items
+                .get(&key)
+                .and_then(|entry: &Entry<T>| entry.live_value(now))
+        };
+        self.search_index
+            .complete(prefix,
+                      limit,
+                      |key| self.meta.owns(key) && live_value(key).is_some())
+            .into_iter()
+            .filter_map(|key| live_value(key).map(|value| value.term().to_string()))
+            .collect()
+    }
+
     pub fn delete(&mut self, key: I::Key) -> NodeResult<bool, I> {
         if self.meta.owns(key) {
-            if self.items.remove(&key).is_some() {
-                self.meta.itemcount -= 1;
-                Ok(true)
-            } else {
-                Ok(false)
-            }
+            // A tombstone is written even if the key is absent here, in case a stale
+            // copy of it is handed over later.
+            let now = timestamp();
+            let existed = self.items
+                .get(&key)
+                .map_or(false, |entry| entry.live_value(now).is_some());
+            self.store(key, Entry::tombstone());
+            Ok(existed)
         } else {
             Err(self.meta.next(key))
         }
@@ -156,3 +702,306 @@ impl<I, T> Node<I, T>
 //         Node::new(u32s)
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u32) -> Id {
+        Id {
+            addr: format!("127.0.0.1:{}", n).parse().unwrap(),
+            key: [n, 0, 0, 0, 0],
+        }
+    }
+
+    fn definitions(n: u32) -> Definitions {
+        Definitions::from(Definition {
+                              urban_id: n as u64,
+                              term: format!("term {}", n),
+                              canonical_term: format!("term {}", n),
+                              definition: format!("definition {}", n),
+                              author: "author".to_string(),
+                              example: String::new(),
+                              thumbs_up: 0,
+                              thumbs_down: 0,
+                          })
+    }
+
+    /// Nodes keyed by `keys`, linked into a ring in key order.
+    fn ring(keys: &[u32]) -> Vec<Node<Id, Definitions>> {
+        let mut keys = keys.to_vec();
+        keys.sort();
+        let n = keys.len();
+        (0..n)
+            .map(|i| {
+                let mut node = Node::new(id(keys[i]));
+                node.meta.relations = Some(NodeRelations {
+                                               predecessor_id: id(keys[(i + n - 1) % n]),
+                                               successor_id: id(keys[(i + 1) % n]),
+                                           });
+                node
+            })
+            .collect()
+    }
+
+    fn store(ring: &mut [Node<Id, Definitions>], key: Key) {
+        let owner = ring.iter_mut()
+            .find(|node| node.meta.owns(key))
+            .expect("No node owns the key.");
+        owner.set(key, definitions(key[0]), None).unwrap();
+    }
+
+    /// Scan the whole ring from the first node the way a client would, following
+    /// continuation tokens and forwarding to the nodes the scan is sent to.
+    fn scan_ring(ring: &[Node<Id, Definitions>], limit: usize) -> Vec<Key> {
+        let mut keys = vec![];
+        let mut start = ScanBound::Included(MIN_KEY);
+        let mut node = 0;
+        for _ in 0..1000 {
+            match ring[node].scan(start, MAX_KEY, limit) {
+                Ok(page) => {
+                    keys.extend(page.items.into_iter().map(|(key, _)| key));
+                    match page.next {
+                        Some(next) => start = next,
+                        None => return keys,
+                    }
+                }
+                Err(next_id) => {
+                    node = ring.iter()
+                        .position(|node| node.meta.id == next_id)
+                        .unwrap();
+                }
+            }
+        }
+        panic!("Scan did not finish.");
+    }
+
+    #[test]
+    fn scan_visits_every_key_once_when_a_range_wraps() {
+        // The node keyed 100 owns both the keys above 300 and those up to 100.
+        let mut ring = ring(&[100, 200, 300]);
+        let mut keys = vec![MIN_KEY, MAX_KEY];
+        keys.extend([50, 100, 150, 200, 250, 300, 350, 400]
+                        .iter()
+                        .map(|&n| [n, 0, 0, 0, 0]));
+        for &key in &keys {
+            store(&mut ring, key);
+        }
+        keys.sort();
+
+        for limit in 1..keys.len() + 2 {
+            assert_eq!(scan_ring(&ring, limit), keys, "limit {}", limit);
+        }
+    }
+
+    #[test]
+    fn scan_of_the_bottom_of_a_wrapping_range_stops_at_the_end() {
+        let mut ring = ring(&[100, 200, 300]);
+        for &n in &[50, 350] {
+            store(&mut ring, [n, 0, 0, 0, 0]);
+        }
+
+        let page = ring[0]
+            .scan(ScanBound::Included(MIN_KEY), [60, 0, 0, 0, 0], 10)
+            .unwrap();
+        let keys = page.items
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![[50, 0, 0, 0, 0]]);
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    fn requeued_reference_changes_are_taken_again() {
+        let mut node = Node::new(id(100));
+        node.set([50, 0, 0, 0, 0], definitions(50), None).unwrap();
+        let changes = node.take_reference_changes();
+        assert!(!changes.is_empty());
+        assert!(node.take_reference_changes().is_empty());
+
+        let count = changes.len();
+        node.requeue_reference_changes(changes);
+        assert_eq!(node.take_reference_changes().len(), count);
+    }
+
+    #[test]
+    fn expiring_entry_with_an_enormous_ttl_never_expires() {
+        let ttl = Duration::from_secs(::std::u64::MAX);
+        let entry = Entry::expiring((), ttl);
+        assert_eq!(entry.expires_at, Some(::std::u64::MAX));
+        assert!(entry.live_value(timestamp()).is_some());
+    }
+
+    #[test]
+    fn updates_increment_votes_in_place() {
+        let mut node = Node::new(id(100));
+        let key = [50, 0, 0, 0, 0];
+        node.set(key, definitions(50), None).unwrap();
+        let up = DefinitionUpdate::IncrementThumbsUp {
+            urban_id: 50,
+            by: 2,
+        };
+        node.update(key, &up).unwrap();
+        let down = DefinitionUpdate::IncrementThumbsDown {
+            urban_id: 50,
+            by: 1,
+        };
+        let updated = node.update(key, &down).unwrap().unwrap().get(50).unwrap().clone();
+        assert_eq!((updated.thumbs_up, updated.thumbs_down), (2, 1));
+
+        let overflow = DefinitionUpdate::IncrementThumbsUp {
+            urban_id: 50,
+            by: ::std::u64::MAX,
+        };
+        node.update(key, &overflow).unwrap();
+        let saturated = node.get(key).unwrap().unwrap().get(50).unwrap().thumbs_up;
+        assert_eq!(saturated, ::std::u64::MAX);
+    }
+
+    #[test]
+    fn updates_of_missing_or_deleted_keys_change_nothing() {
+        let mut node = Node::new(id(100));
+        let key = [50, 0, 0, 0, 0];
+        let up = DefinitionUpdate::IncrementThumbsUp {
+            urban_id: 50,
+            by: 1,
+        };
+        assert!(node.update(key, &up).unwrap().is_none());
+        node.set(key, definitions(50), None).unwrap();
+        node.delete(key).unwrap();
+        assert!(node.update(key, &up).unwrap().is_none());
+        assert!(node.get(key).unwrap().is_none());
+    }
+
+    #[test]
+    fn merge_updates_keep_the_higher_scoring_definition() {
+        let mut node = Node::new(id(100));
+        let key = [50, 0, 0, 0, 0];
+        node.set(key, definitions(50), None).unwrap();
+        let mut better = definitions(50).get(50).unwrap().clone();
+        better.thumbs_up = 3;
+        better.definition = "better".to_string();
+        let mut worse = better.clone();
+        worse.thumbs_up = 0;
+        worse.thumbs_down = 1;
+        worse.definition = "worse".to_string();
+
+        node.update(key, &DefinitionUpdate::Merge(better)).unwrap();
+        node.update(key, &DefinitionUpdate::Merge(worse)).unwrap();
+        let stored = node.get(key).unwrap().unwrap().get(50).unwrap().clone();
+        assert_eq!(stored.definition, "better");
+    }
+
+    #[test]
+    fn deleted_keys_read_as_absent() {
+        let mut node = Node::new(id(100));
+        let key = [50, 0, 0, 0, 0];
+        node.set(key, definitions(50), None).unwrap();
+        assert!(node.delete(key).unwrap());
+        assert!(!node.delete(key).unwrap());
+        assert!(!node.exists(key).unwrap());
+        assert!(node.get(key).unwrap().is_none());
+        assert!(node.items[&key].is_tombstone());
+        assert_eq!(node.meta.itemcount, 0);
+    }
+
+    #[test]
+    fn tombstones_win_over_older_copies_handed_over() {
+        let mut node = Node::new(id(100));
+        let key = [50, 0, 0, 0, 0];
+        let mut stale = Entry::new(definitions(50));
+        node.delete(key).unwrap();
+        stale.timestamp = node.items[&key].timestamp - 1;
+        assert!(!node.merge(key, stale.clone()));
+        assert!(node.get(key).unwrap().is_none());
+
+        // A tie goes to the tombstone too.
+        stale.timestamp += 1;
+        assert!(!node.merge(key, stale));
+        assert!(node.get(key).unwrap().is_none());
+    }
+
+    #[test]
+    fn tombstones_are_collected_after_the_grace_period() {
+        let mut node = Node::<Id, Definitions>::new(id(100));
+        let key = [50, 0, 0, 0, 0];
+        node.delete(key).unwrap();
+        assert_eq!(node.collect_tombstones(), 0);
+        assert!(node.items.contains_key(&key));
+
+        node.tombstone_grace = Duration::from_secs(0);
+        assert_eq!(node.collect_tombstones(), 1);
+        assert!(!node.items.contains_key(&key));
+    }
+
+    fn expired(n: u32) -> Entry<Definitions> {
+        let mut entry = Entry::expiring(definitions(n), Duration::from_secs(1));
+        entry.timestamp -= 2000;
+        entry.expires_at = Some(entry.timestamp + 1000);
+        entry
+    }
+
+    #[test]
+    fn expired_values_read_as_absent_and_are_collected() {
+        let mut node = Node::new(id(100));
+        let (live, gone) = ([40, 0, 0, 0, 0], [50, 0, 0, 0, 0]);
+        node.set(live, definitions(40), Some(Duration::from_secs(3600))).unwrap();
+        node.merge(gone, expired(50));
+        assert!(node.exists(live).unwrap());
+        assert!(!node.exists(gone).unwrap());
+        assert!(node.get(gone).unwrap().is_none());
+
+        assert!(node.collect_expired() >= 1);
+        assert!(!node.items.contains_key(&gone));
+        assert!(node.items.contains_key(&live));
+        assert_eq!(node.meta.itemcount, 1);
+    }
+
+    #[test]
+    fn expiry_moves_with_keys_handed_over() {
+        let mut node = Node::<Id, Definitions>::new(id(200));
+        let key = [150, 0, 0, 0, 0];
+        let entry = Entry::expiring(definitions(150), Duration::from_secs(3600));
+        let expires_at = entry.expires_at;
+        let mut transfer_items = HashMap::new();
+        transfer_items.insert(key, entry);
+        node.apply_precede_reply(PrecedeReply {
+                                     predecessor_id: id(100),
+                                     successor_id: id(300),
+                                     transfer_items: transfer_items,
+                                     transfer_references: HashMap::new(),
+                                 });
+        assert_eq!(node.items[&key].expires_at, expires_at);
+    }
+
+    #[test]
+    fn setting_a_key_again_replaces_its_ttl() {
+        let mut node = Node::new(id(100));
+        let key = [50, 0, 0, 0, 0];
+        node.set(key, definitions(50), Some(Duration::from_secs(60))).unwrap();
+        node.set(key, definitions(51), None).unwrap();
+        assert!(node.items[&key].expires_at.is_none());
+        assert_eq!(node.get(key).unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn hand_over_moves_only_the_new_predecessors_range() {
+        let mut ring = ring(&[100, 300]);
+        for &n in &[150, 250, 350] {
+            store(&mut ring, [n, 0, 0, 0, 0]);
+        }
+        let node = &mut ring[1];
+        node.delete([160, 0, 0, 0, 0]).unwrap();
+        node.meta.relations.as_mut().unwrap().predecessor_id = id(200);
+        node.hand_over();
+
+        let mut handed_over = node.handed_over_items.keys().cloned().collect::<Vec<_>>();
+        handed_over.sort();
+        assert_eq!(handed_over, vec![[150, 0, 0, 0, 0], [160, 0, 0, 0, 0]]);
+        assert_eq!(node.items.keys().cloned().collect::<Vec<_>>(), vec![[250, 0, 0, 0, 0]]);
+        assert_eq!(node.meta.itemcount, 1);
+        assert!(node.misplaced_keys().is_empty());
+        assert!(node.search("150", 10).is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+use super::*;
+
+/// How a term is normalized before it is hashed into a key. Importers and clients must
+/// agree on this, or the same term will land under different keys. Steps missing from a
+/// config file are on, as they are by default.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Normalization {
+    /// Apply Unicode compatibility composition (NFKC).
+    pub nfkc: bool,
+    /// Lowercase the term. This is full Unicode lowercasing rather than strict case
+    /// folding, which agrees for almost all terms.
+    pub case_fold: bool,
+    /// Trim leading and trailing whitespace and collapse internal runs to one space.
+    pub trim: bool,
+}
+
+impl Normalization {
+    /// Hash terms exactly as given.
+    pub fn none() -> Normalization {
+        Normalization {
+            nfkc: false,
+            case_fold: false,
+            trim: false,
+        }
+    }
+
+    pub fn apply(&self, term: &str) -> String {
+        let mut term = if self.nfkc {
+            term.nfkc().collect::<String>()
+        } else {
+            term.to_string()
+        };
+        if self.case_fold {
+            term = term.to_lowercase();
+        }
+        if self.trim {
+            term = term.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        term
+    }
+}
+
+impl Default for Normalization {
+    fn default() -> Normalization {
+        Normalization {
+            nfkc: true,
+            case_fold: true,
+            trim: true,
+        }
+    }
+}
+
+/// Reads the steps to apply as a comma-separated list of `nfkc`, `case_fold` and `trim`,
+/// or `none` for none of them.
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(steps: &str) -> Result<Normalization, String> {
+        let mut normalization = Normalization::none();
+        for step in steps.split(',').map(|step| step.trim()) {
+            match step {
+                "nfkc" => normalization.nfkc = true,
+                "case_fold" => normalization.case_fold = true,
+                "trim" => normalization.trim = true,
+                "none" | "" => {}
+                _ => return Err(format!("Unknown normalization step {:?}.", step)),
+            }
+        }
+        Ok(normalization)
+    }
+}
+
+/// The key a term is stored under.
+pub fn term_key(term: &str, normalization: &Normalization) -> Key {
+    canonical_hash(normalization.apply(term))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_normalization_gives_variants_of_a_term_one_key() {
+        let key = term_key("yeet", &Normalization::default());
+        for variant in &["Yeet", "yeet ", "YEET", "  ye\u{ff45}t", "\tYEET\n"] {
+            assert_eq!(term_key(variant, &Normalization::default()), key, "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn each_step_can_be_turned_off() {
+        let only = |nfkc, case_fold, trim| {
+            Normalization {
+                nfkc: nfkc,
+                case_fold: case_fold,
+                trim: trim,
+            }
+        };
+        assert_eq!(only(true, false, false).apply(" \u{fb01}ne "), " fine ");
+        assert_eq!(only(false, true, false).apply(" Fine "), " fine ");
+        assert_eq!(only(false, false, true).apply("  so   Fine "), "so Fine");
+        assert_eq!(Normalization::none().apply(" \u{fb01}ne  X"), " \u{fb01}ne  X");
+    }
+
+    #[test]
+    fn steps_are_read_from_a_list() {
+        assert_eq!("nfkc, case_fold,trim".parse(), Ok(Normalization::default()));
+        assert_eq!("none".parse(), Ok(Normalization::none()));
+        assert_eq!("".parse(), Ok(Normalization::none()));
+        assert_eq!("trim".parse::<Normalization>().map(|normalization| normalization.trim),
+                   Ok(true));
+        assert!("lowercase".parse::<Normalization>().is_err());
+    }
+
+    #[test]
+    fn unnormalized_keys_differ_by_case() {
+        assert_ne!(term_key("Yeet", &Normalization::none()),
+                   term_key("yeet", &Normalization::none()));
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt::Debug;
 use std::collections::HashMap;
+use std::time::Duration;
 use super::*;
 
 // query engine, resolver, database,
@@ -46,6 +47,48 @@ pub enum QueryResult<I, T>
 //     pub query_for_reply: QueryForReply<I, T>,
 // }
 
+/// A batch query's outcome: the part answered by the local node, and the items it does
+/// not own, left for the caller to send on to their owners.
+pub struct BatchResult<A, R> {
+    pub answers: Vec<A>,
+    pub unowned: Vec<R>,
+}
+
+impl<A, R> BatchResult<A, R> {
+    pub fn new() -> BatchResult<A, R> {
+        BatchResult {
+            answers: vec![],
+            unowned: vec![],
+        }
+    }
+}
+
+/// Items grouped by the node each is going to, so that every node gets one batch.
+pub struct Batches<I, R>
+    where I: NodeId
+{
+    pub batches: Vec<(I, Vec<R>)>,
+}
+
+impl<I, R> Batches<I, R>
+    where I: NodeId
+{
+    pub fn new() -> Batches<I, R> {
+        Batches { batches: vec![] }
+    }
+
+    pub fn add(&mut self, node_id: I, item: R) {
+        if let Some(&mut (_, ref mut items)) =
+            self.batches
+                .iter_mut()
+                .find(|&&mut (id, _)| id.key() == node_id.key()) {
+            items.push(item);
+            return;
+        }
+        self.batches.push((node_id, vec![item]));
+    }
+}
+
 pub struct OwnerQuery<I>
     where I: NodeId
 {
@@ -65,7 +108,8 @@ pub struct PrecedeReply<I, T>
 {
     pub predecessor_id: I,
     pub successor_id: I,
-    pub transfer_items: HashMap<I::Key, T>,
+    pub transfer_items: HashMap<I::Key, Entry<T>>,
+    pub transfer_references: HashMap<I::Key, HashMap<I::Key, Entry<()>>>,
 }
 
 pub struct ExistsQuery<I>
@@ -86,6 +130,95 @@ pub struct SetQuery<I, T>
 {
     pub key: I::Key,
     pub value: T,
+    pub ttl: Option<Duration>,
+}
+
+pub struct GetManyQuery<I>
+    where I: NodeId
+{
+    pub keys: Vec<I::Key>,
+}
+
+pub struct SetManyQuery<I, T>
+    where I: NodeId,
+          T: Clone + Debug
+{
+    /// Each item with the TTL to give it, if it is to expire.
+    pub items: Vec<(I::Key, T, Option<Duration>)>,
+}
+
+/// Where a scan starts or resumes: at a key, or just past one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScanBound<K> {
+    Included(K),
+    Excluded(K),
+}
+
+impl<K> ScanBound<K>
+    where K: Copy + Ord
+{
+    pub fn key(&self) -> K {
+        match *self {
+            ScanBound::Included(key) |
+            ScanBound::Excluded(key) => key,
+        }
+    }
+
+    pub fn contains(&self, key: K) -> bool {
+        match *self {
+            ScanBound::Included(start) => key >= start,
+            ScanBound::Excluded(start) => key > start,
+        }
+    }
+}
+
+pub struct ScanQuery<I>
+    where I: NodeId
+{
+    pub start: ScanBound<I::Key>,
+    pub end: I::Key,
+    pub limit: usize,
+}
+
+/// One node's worth of a scan. `next` is the continuation token to pass as the start of
+/// the following scan; `None` means the scan is complete.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanPage<K, T> {
+    pub items: Vec<(K, T)>,
+    /// When those of `items` that expire do so.
+    pub expiring: Vec<(K, Timestamp)>,
+    pub next: Option<ScanBound<K>>,
+}
+
+pub struct SearchQuery {
+    pub query: String,
+    pub limit: usize,
+}
+
+pub struct AutocompleteQuery {
+    pub prefix: String,
+    pub limit: usize,
+}
+
+pub struct ReferenceQuery<I>
+    where I: NodeId
+{
+    pub index_key: I::Key,
+    pub primary_key: I::Key,
+    pub entry: Entry<()>,
+}
+
+pub struct ReferencesQuery<I>
+    where I: NodeId
+{
+    pub index_key: I::Key,
+}
+
+pub struct UpdateQuery<I, U>
+    where I: NodeId
+{
+    pub key: I::Key,
+    pub update: U,
 }
 
 pub struct DeleteQuery<I>
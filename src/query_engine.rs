@@ -1,5 +1,7 @@
 use std::fmt::Debug;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use slog::Logger;
 use super::*;
 
 #[derive(Clone)]
@@ -8,15 +10,19 @@ pub struct QueryEngine<I, T>
           T: Clone + Debug + Send + 'static
 {
     pub local_node: Arc<RwLock<Node<I, T>>>,
+    pub log: Logger,
 }
 
 impl<I, T> QueryEngine<I, T>
     where I: NodeId,
-          T: Clone + Debug + Send + 'static
+          T: Clone + Debug + Send + Merge + Document + Indexed<I::Key> + 'static
 {
     pub fn new(local_node: Node<I, T>) -> QueryEngine<I, T> {
         let local_node = Arc::new(RwLock::new(local_node));
-        QueryEngine { local_node }
+        QueryEngine {
+            local_node: local_node,
+            log: discard_logger(),
+        }
     }
 
     // pub fn query(&self, query: Query<I, T>) -> BoxFuture<(), ()> {
@@ -42,7 +48,7 @@ impl<I, T> QueryEngine<I, T>
         if local_node.meta.owns(query.key) {
             QueryResult::Answer(local_node.meta.id)
         } else {
-            QueryResult::Node(local_node.meta.next(local_node.meta.id.key()))
+            self.pass_on("owner", query.key, local_node.meta.next(local_node.meta.id.key()))
         }
     }
 
@@ -50,7 +56,34 @@ impl<I, T> QueryEngine<I, T>
         let mut local_node = self.local_node
             .write()
             .expect("Could not acquire node.");
-        if local_node.meta.owns(query.id.key()) {
+        let key = query.id.key();
+        if let Some(relations) = local_node.meta.relations {
+            // The node already joined, but may not have heard back: answer it again from
+            // the predecessor it replaced, and route it back to here from the node it
+            // follows. This holds until another node joins next to it.
+            if relations.predecessor_id.key() == key {
+                if let Some(predecessor_id) = local_node.previous_predecessor_id {
+                    info!(self.log, "Predecessor joined again";
+                          "predecessor" => format!("{:?}", query.id));
+                    return QueryResult::Answer(PrecedeReply {
+                                                   predecessor_id: predecessor_id,
+                                                   successor_id: local_node.meta.id,
+                                                   transfer_items: local_node
+                                                       .handed_over_items
+                                                       .clone(),
+                                                   transfer_references: local_node
+                                                       .handed_over_references
+                                                       .clone(),
+                                               });
+                }
+            }
+            if relations.successor_id.key() == key && !local_node.meta.owns(key) {
+                if let Some(successor_id) = local_node.previous_successor_id {
+                    return self.pass_on("precede", key, successor_id);
+                }
+            }
+        }
+        if local_node.meta.owns(key) {
             let mut predecessor_id = local_node.meta.id;
             local_node.meta.relations =
                 Some(local_node
@@ -67,13 +100,23 @@ impl<I, T> QueryEngine<I, T>
                                           relations.predecessor_id = query.id;
                                           relations
                                       }));
+            local_node.previous_predecessor_id = Some(predecessor_id);
+            // The new predecessor takes over the keys between it and the old one.
+            local_node.hand_over();
+            info!(self.log, "New predecessor";
+                  "predecessor" => format!("{:?}", query.id),
+                  "previous" => format!("{:?}", predecessor_id),
+                  "transferred" => local_node.handed_over_items.len());
             QueryResult::Answer(PrecedeReply {
                                     predecessor_id: predecessor_id,
                                     successor_id: local_node.meta.id,
-                                    transfer_items: local_node.items.clone(),
+                                    transfer_items: local_node.handed_over_items.clone(),
+                                    transfer_references: local_node
+                                        .handed_over_references
+                                        .clone(),
                                 })
         } else {
-            QueryResult::Node(local_node.meta.next(local_node.meta.id.key()))
+            self.pass_on("precede", key, local_node.meta.next(local_node.meta.id.key()))
         }
     }
 
@@ -81,7 +124,7 @@ impl<I, T> QueryEngine<I, T>
         let local_node = self.local_node.read().expect("Could not acquire node.");
         match local_node.exists(query.key) {
             Ok(answer) => QueryResult::Answer(answer),
-            Err(next_id) => QueryResult::Node(next_id),
+            Err(next_id) => self.pass_on("exists", query.key, next_id),
         }
     }
 
@@ -89,7 +132,7 @@ impl<I, T> QueryEngine<I, T>
         let local_node = self.local_node.read().expect("Could not acquire node.");
         match local_node.get(query.key) {
             Ok(answer) => QueryResult::Answer(answer.cloned()),
-            Err(next_id) => QueryResult::Node(next_id),
+            Err(next_id) => self.pass_on("get", query.key, next_id),
         }
     }
 
@@ -97,9 +140,105 @@ impl<I, T> QueryEngine<I, T>
         let mut local_node = self.local_node
             .write()
             .expect("Could not acquire node.");
-        match local_node.set(query.key, query.value.clone()) {
+        match local_node.set(query.key, query.value.clone(), query.ttl) {
+            Ok(()) => QueryResult::Answer(()),
+            Err(next_id) => self.pass_on("set", query.key, next_id),
+        }
+    }
+
+    pub fn get_many(&self, query: GetManyQuery<I>) -> BatchResult<(I::Key, Option<T>), I::Key> {
+        let local_node = self.local_node.read().expect("Could not acquire node.");
+        let mut result = BatchResult::new();
+        for key in query.keys {
+            match local_node.get(key) {
+                Ok(answer) => result.answers.push((key, answer.cloned())),
+                Err(_) => result.unowned.push(key),
+            }
+        }
+        result
+    }
+
+    pub fn set_many(&self,
+                    query: SetManyQuery<I, T>)
+                    -> BatchResult<I::Key, (I::Key, T, Option<Duration>)> {
+        let mut local_node = self.local_node
+            .write()
+            .expect("Could not acquire node.");
+        let mut result = BatchResult::new();
+        for (key, value, ttl) in query.items {
+            if local_node.meta.owns(key) {
+                local_node
+                    .set(key, value, ttl)
+                    .expect("Owned key was not set.");
+                result.answers.push(key);
+            } else {
+                result.unowned.push((key, value, ttl));
+            }
+        }
+        result
+    }
+
+    pub fn scan(&self, query: ScanQuery<I>) -> QueryResult<I, ScanPage<I::Key, T>> {
+        let local_node = self.local_node.read().expect("Could not acquire node.");
+        match local_node.scan(query.start, query.end, query.limit) {
+            Ok(answer) => QueryResult::Answer(answer),
+            Err(next_id) => self.pass_on("scan", query.start.key(), next_id),
+        }
+    }
+
+    pub fn search(&self, query: SearchQuery) -> Vec<SearchHit<I::Key, T>> {
+        let local_node = self.local_node.read().expect("Could not acquire node.");
+        local_node.search(&query.query, query.limit)
+    }
+
+    pub fn autocomplete(&self, query: AutocompleteQuery) -> Vec<String> {
+        let local_node = self.local_node.read().expect("Could not acquire node.");
+        local_node.autocomplete(&query.prefix, query.limit)
+    }
+
+    pub fn reference(&self, query: ReferenceQuery<I>) -> QueryResult<I, ()> {
+        let mut local_node = self.local_node
+            .write()
+            .expect("Could not acquire node.");
+        match local_node.reference(query.index_key, query.primary_key, query.entry) {
             Ok(()) => QueryResult::Answer(()),
-            Err(next_id) => QueryResult::Node(next_id),
+            Err(next_id) => self.pass_on("reference", query.index_key, next_id),
+        }
+    }
+
+    pub fn references(&self, query: ReferencesQuery<I>) -> QueryResult<I, Vec<I::Key>> {
+        let local_node = self.local_node.read().expect("Could not acquire node.");
+        match local_node.references(query.index_key) {
+            Ok(answer) => QueryResult::Answer(answer),
+            Err(next_id) => self.pass_on("references", query.index_key, next_id),
+        }
+    }
+
+    /// Reference changes made by local writes that still need sending to the nodes
+    /// owning their secondary keys.
+    pub fn take_reference_changes(&self) -> Vec<ReferenceChange<I::Key>> {
+        let mut local_node = self.local_node
+            .write()
+            .expect("Could not acquire node.");
+        local_node.take_reference_changes()
+    }
+
+    pub fn requeue_reference_changes(&self, changes: Vec<ReferenceChange<I::Key>>) {
+        let mut local_node = self.local_node
+            .write()
+            .expect("Could not acquire node.");
+        local_node.requeue_reference_changes(changes)
+    }
+
+    pub fn update<U>(&self, query: UpdateQuery<I, U>) -> QueryResult<I, Option<T>>
+        where U: Update<T>
+    {
+        let mut local_node = self.local_node
+            .write()
+            .expect("Could not acquire node.");
+        match local_node.update(query.key, &query.update) {
+            Ok(answer) => QueryResult::Answer(answer.cloned()),
+            Err(next_id) => self.pass_on("update", query.key, next_id),
         }
     }
 
@@ -109,7 +248,100 @@ impl<I, T> QueryEngine<I, T>
             .expect("Could not acquire node.");
         match node.delete(query.key) {
             Ok(answer) => QueryResult::Answer(answer),
-            Err(next_id) => QueryResult::Node(next_id),
+            Err(next_id) => self.pass_on("delete", query.key, next_id),
         }
     }
+
+    pub fn misplaced_keys(&self) -> Vec<I::Key> {
+        let local_node = self.local_node.read().expect("Could not acquire node.");
+        local_node.misplaced_keys()
+    }
+
+    /// Passes a query for `key`, which this node does not own, on to `next_id`.
+    fn pass_on<A>(&self, query: &'static str, key: I::Key, next_id: I) -> QueryResult<I, A>
+        where A: Clone + Debug
+    {
+        debug!(self.log, "Not the owner, passing on";
+               "query" => query,
+               "key" => format!("{:?}", key),
+               "next" => format!("{:?}", next_id));
+        QueryResult::Node(next_id)
+    }
+
+    /// Housekeeping on the local node, meant to be run periodically.
+    pub fn collect_garbage(&self) -> usize {
+        let mut local_node = self.local_node
+            .write()
+            .expect("Could not acquire node.");
+        let collected = local_node.collect_tombstones() + local_node.collect_expired();
+        if collected > 0 {
+            debug!(self.log, "Collected garbage"; "entries" => collected);
+        }
+        collected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u32) -> Id {
+        Id {
+            addr: format!("127.0.0.1:{}", n).parse().unwrap(),
+            key: [n, 0, 0, 0, 0],
+        }
+    }
+
+    fn engine(n: u32, predecessor: u32, successor: u32) -> QueryEngine<Id, Definitions> {
+        let mut node = Node::new(id(n));
+        node.meta.relations = Some(NodeRelations {
+                                       predecessor_id: id(predecessor),
+                                       successor_id: id(successor),
+                                   });
+        QueryEngine::new(node)
+    }
+
+    fn answer(result: QueryResult<Id, PrecedeReply<Id, Definitions>>) -> (Id, Id) {
+        match result {
+            QueryResult::Answer(reply) => (reply.predecessor_id, reply.successor_id),
+            QueryResult::Node(node_id) => panic!("Forwarded to {:?}.", node_id),
+        }
+    }
+
+    #[test]
+    fn precede_answers_a_node_joining_again_the_same_way() {
+        let low = engine(100, 300, 300);
+        let high = engine(300, 100, 100);
+
+        let first = answer(high.precede(PrecedeQuery { id: id(200) }));
+        assert_eq!(first, (id(100), id(300)));
+        assert!(low.local_node.write().unwrap().set_successor(id(200)));
+
+        // The joining node never heard back, and tries again through the low node.
+        match low.precede(PrecedeQuery { id: id(200) }) {
+            QueryResult::Node(node_id) => assert_eq!(node_id, id(300)),
+            QueryResult::Answer(_) => panic!("Answered a join it does not own."),
+        }
+        assert_eq!(answer(high.precede(PrecedeQuery { id: id(200) })), first);
+        let relations = high.local_node.read().unwrap().meta.relations.unwrap();
+        assert_eq!(relations.predecessor_id, id(200));
+    }
+
+    #[test]
+    fn precede_by_the_first_node_to_join_is_answered_again_by_the_lone_node() {
+        let lone = QueryEngine::<Id, Definitions>::new(Node::new(id(100)));
+        let first = answer(lone.precede(PrecedeQuery { id: id(200) }));
+        assert_eq!(first, (id(100), id(100)));
+        assert_eq!(answer(lone.precede(PrecedeQuery { id: id(200) })), first);
+    }
+
+    #[test]
+    fn set_successor_keeps_the_previous_one_when_unchanged() {
+        let low = engine(100, 300, 300);
+        assert!(low.local_node.write().unwrap().set_successor(id(200)));
+        assert!(low.local_node.write().unwrap().set_successor(id(200)));
+        let node = low.local_node.read().unwrap();
+        assert_eq!(node.previous_successor_id, Some(id(300)));
+        assert!(!Node::<Id, Definitions>::new(id(1)).set_successor(id(2)));
+    }
 }
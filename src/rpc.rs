@@ -1,17 +1,28 @@
+use std::io;
 use std::net::SocketAddr;
 use std::cmp::Ordering;
+use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
-use std::time::Duration;
-use futures::{future, Future};
+use std::time::{Duration, Instant};
+use futures::{future, Future, Stream};
 use tarpc::future::client;
 use tarpc::future::client::ClientExt;
-use futures::future::Either;
+use futures::future::{Either, Loop};
 use tokio_timer::*;
+use slog::{Level, Logger};
 use super::*;
 
 pub type Key = [u32; 5];
 
+pub const MIN_KEY: Key = [0; 5];
+pub const MAX_KEY: Key = [::std::u32::MAX; 5];
+
+/// Most nodes a walk of the ring visits before assuming it is broken.
+pub const MAX_RING_HOPS: usize = 1024;
+
+/// A node as known to the rest of the ring. `addr` is the address peers connect to,
+/// which is not necessarily the one the node binds: see `NodeAddrs`.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Id {
     pub addr: SocketAddr,
@@ -55,7 +66,48 @@ impl From<SocketAddr> for Id {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Where a node binds and where peers should reach it. These differ behind NAT, inside a
+/// container, or when binding a wildcard address like `0.0.0.0`. Only the advertised
+/// address goes into the node's `Id`, and so into its key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NodeAddrs {
+    pub bind: SocketAddr,
+    pub advertise: SocketAddr,
+}
+
+impl NodeAddrs {
+    /// Advertise `bind` itself unless told otherwise.
+    pub fn new(bind: SocketAddr, advertise: Option<SocketAddr>) -> NodeAddrs {
+        NodeAddrs {
+            bind: bind,
+            advertise: advertise.unwrap_or(bind),
+        }
+    }
+
+    /// Fill in the ports left as 0 with the one actually bound.
+    pub fn bound_to(&self, bound: SocketAddr) -> NodeAddrs {
+        let mut addrs = *self;
+        if addrs.bind.port() == 0 {
+            addrs.bind.set_port(bound.port());
+        }
+        if addrs.advertise.port() == 0 {
+            addrs.advertise.set_port(bound.port());
+        }
+        addrs
+    }
+
+    /// Whether other hosts have a chance of connecting to the advertised address.
+    pub fn is_routable(&self) -> bool {
+        let ip = self.advertise.ip();
+        !ip.is_unspecified() && !ip.is_loopback()
+    }
+
+    pub fn id(&self) -> Id {
+        Id::from(self.advertise)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum TimeoutErr<E> {
     FutureErr(E),
     TimedOut,
@@ -67,33 +119,219 @@ impl<E, F> From<TimeoutError<F>> for TimeoutErr<E> {
     }
 }
 
+/// The errors RPCs end in, classified for `Metrics`.
+pub trait RpcError {
+    fn outcome(&self) -> Outcome;
+}
+
+impl<E> RpcError for TimeoutErr<E> {
+    fn outcome(&self) -> Outcome {
+        match *self {
+            TimeoutErr::TimedOut => Outcome::TimedOut,
+            TimeoutErr::FutureErr(_) => Outcome::Error,
+        }
+    }
+}
+
+impl RpcError for bool {
+    fn outcome(&self) -> Outcome {
+        Outcome::Error
+    }
+}
+
+/// Logs why a forwarded request failed, leaving the caller with a plain error.
+fn forward_failed<E: Debug>(log: &Logger, e: E) -> TimeoutErr<bool> {
+    warn!(log, "Forwarded request failed"; "error" => format!("{:?}", e));
+    TimeoutErr::FutureErr(false)
+}
+
+/// The outcome for one key of a batch, which fails key by key rather than as a whole.
+pub type KeyResult<T> = Result<T, TimeoutErr<bool>>;
+
+/// An answer put together from every node in the ring, short of those in `unreachable`,
+/// which either could not be found or did not answer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RingAnswer<T> {
+    pub answer: T,
+    pub unreachable: Vec<Id>,
+}
+
+/// What client helpers return: the reply of an RPC, or an error from the network or
+/// the ring.
+pub type ClientFuture<T> = Box<Future<Item = T, Error = ::tarpc::Error<TimeoutErr<bool>>>>;
+
 service! {
     rpc meta() -> NodeMeta<Id> | TimeoutErr<bool>;
     rpc owner(key: Key) -> Id | TimeoutErr<bool>;
     rpc rename(new_node_id: Id) -> bool | bool;
     rpc join(existing_node_id: Id) -> bool | bool;
-    rpc precede(predecessor_id: Id) -> PrecedeReply<Id, Definition> | TimeoutErr<bool>;
+    rpc precede(predecessor_id: Id) -> PrecedeReply<Id, Definitions> | TimeoutErr<bool>;
     rpc succeed(successor_id: Id) -> bool | bool;
     rpc exists(key: Key) -> bool | TimeoutErr<bool>;
     rpc get(key: Key) -> Option<Definition> | TimeoutErr<bool>;
+    rpc get_top(key: Key, n: usize) -> Vec<Definition> | TimeoutErr<bool>;
     rpc set(key: Key, value: Definition) -> () | TimeoutErr<bool>;
+    rpc set_expiring(key: Key, value: Definition, ttl: Duration) -> () | TimeoutErr<bool>;
+    rpc get_many(keys: Vec<Key>) -> Vec<(Key, KeyResult<Option<Definitions>>)> | TimeoutErr<bool>;
+    rpc set_many(items: Vec<(Key, Definitions, Option<Duration>)>)
+        -> Vec<(Key, KeyResult<()>)> | TimeoutErr<bool>;
+    rpc scan(start: ScanBound<Key>, end_key: Key, limit: usize)
+        -> ScanPage<Key, Definitions> | TimeoutErr<bool>;
+    rpc search(query: String, limit: usize)
+        -> RingAnswer<Vec<SearchHit<Key, Definitions>>> | TimeoutErr<bool>;
+    rpc search_local(query: String, limit: usize)
+        -> Vec<SearchHit<Key, Definitions>> | TimeoutErr<bool>;
+    rpc autocomplete(prefix: String, limit: usize) -> RingAnswer<Vec<String>> | TimeoutErr<bool>;
+    rpc autocomplete_local(prefix: String, limit: usize) -> Vec<String> | TimeoutErr<bool>;
+    rpc reference(index_key: Key, primary_key: Key, entry: Entry<()>) -> () | TimeoutErr<bool>;
+    rpc references(index_key: Key) -> Vec<Key> | TimeoutErr<bool>;
+    rpc by_author(author: String) -> Vec<Definition> | TimeoutErr<bool>;
+    rpc by_urban_id(urban_id: u64) -> Option<Definition> | TimeoutErr<bool>;
+    rpc update(key: Key, update: DefinitionUpdate) -> Option<Definition> | TimeoutErr<bool>;
     rpc delete(key: Key) -> bool | TimeoutErr<bool>;
+    rpc topology() -> Topology | TimeoutErr<bool>;
+    rpc misplaced_keys() -> Vec<Key> | TimeoutErr<bool>;
+    rpc set_log_level(level: String) -> bool | bool;
+    rpc health() -> Health | bool;
+    rpc drain() -> bool | bool;
+}
+
+impl FutureClient {
+    /// Fetch the best definition of `term`, normalizing it the same way as the importer
+    /// that stored it.
+    pub fn get_by_term(&self,
+                       term: &str,
+                       normalization: &Normalization)
+                       -> ClientFuture<Option<Definition>> {
+        box self.get(term_key(term, normalization))
+    }
 }
 
 #[derive(Clone)]
 pub struct ChordServer {
-    query_engine: QueryEngine<Id, Definition>,
+    query_engine: QueryEngine<Id, Definitions>,
     client_pool: Arc<Mutex<HashMap<Id, FutureClient>>>,
     timer: Timer,
+    request_timeout: Duration,
+    metrics: Metrics,
+    log: Logger,
+    log_level: LogLevel,
+    joining: Arc<Mutex<bool>>,
+    leaving: Arc<Mutex<bool>>,
+    transfers: Arc<Mutex<usize>>,
+}
+
+/// A transfer of keys to or from a neighbouring node, reported by `health` until
+/// dropped.
+struct Transfer(Arc<Mutex<usize>>);
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        *self.0.lock().unwrap() -= 1;
+    }
 }
 
 impl ChordServer {
-    pub fn new(query_engine: QueryEngine<Id, Definition>) -> ChordServer {
+    pub fn new(query_engine: QueryEngine<Id, Definitions>) -> ChordServer {
         ChordServer {
             query_engine: query_engine,
             client_pool: Arc::new(Mutex::new(HashMap::new())),
             timer: Timer::default(),
+            request_timeout: Duration::from_secs(8),
+            metrics: Metrics::new(),
+            log: discard_logger(),
+            log_level: LogLevel::new(Level::Info),
+            joining: Arc::new(Mutex::new(false)),
+            leaving: Arc::new(Mutex::new(false)),
+            transfers: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Log through `log`, whose level `set_log_level` changes through `log_level`.
+    /// Nothing is logged otherwise.
+    pub fn logger(mut self, log: Logger, log_level: LogLevel) -> ChordServer {
+        let id = self.query_engine.local_node.read().unwrap().meta.id;
+        self.log = log.new(o!("node" => id.addr.to_string()));
+        self.query_engine.log = self.log.clone();
+        self.log_level = log_level;
+        self
+    }
+
+    /// How long to wait on a request forwarded to another node. Defaults to 8 seconds.
+    pub fn request_timeout(mut self, request_timeout: Duration) -> ChordServer {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Reports the node as joining rather than standing alone until a join succeeds.
+    /// `join` does this itself; whoever starts a node that is to join a ring calls it up
+    /// front, so the node is not reported ready while it waits on its seeds.
+    pub fn joining(&self) {
+        *self.joining.lock().unwrap() = true;
+    }
+
+    /// Marks the node as transferring keys until the returned `Transfer` is dropped.
+    /// Transfers with several joining nodes may overlap.
+    fn transfer(&self) -> Transfer {
+        *self.transfers.lock().unwrap() += 1;
+        Transfer(self.transfers.clone())
+    }
+
+    /// Runs `call`, which answers the RPC named `rpc`, recording its latency and outcome.
+    fn observe<T, E, F>(&self, rpc: &'static str, call: F) -> Box<Future<Item = T, Error = E>>
+        where T: 'static,
+              E: RpcError + Debug + 'static,
+              F: FnOnce() -> Box<Future<Item = T, Error = E>>
+    {
+        let metrics = self.metrics.clone();
+        let log = self.log.new(o!("rpc" => rpc));
+        let started = Instant::now();
+        box call().then(move |result| {
+            let latency = started.elapsed();
+            let latency_ms = latency.as_secs() * 1000 + (latency.subsec_nanos() / 1_000_000) as u64;
+            let outcome = match result {
+                Ok(_) => {
+                    debug!(log, "Answered"; "latency_ms" => latency_ms);
+                    Outcome::Ok
+                }
+                Err(ref e) => {
+                    warn!(log, "Failed";
+                          "latency_ms" => latency_ms,
+                          "error" => format!("{:?}", e));
+                    e.outcome()
+                }
+            };
+            metrics.observe(rpc, latency, outcome);
+            result
+        })
+    }
+
+    /// Counts a hop of `rpc` to `peer`, returning a logger for the forwarded request.
+    fn forwarding(&self, rpc: &'static str, peer: Id, key: Option<Key>) -> Logger {
+        self.metrics.forwarded(rpc);
+        let log = self.peer_logger(rpc, peer, 1);
+        match key {
+            Some(key) => debug!(log, "Forwarding"; "key" => key_hex(&key)),
+            None => debug!(log, "Forwarding"),
         }
+        log
+    }
+
+    /// A logger for a request of `rpc` to `peer`, `hops` nodes away from this one.
+    fn peer_logger(&self, rpc: &'static str, peer: Id, hops: usize) -> Logger {
+        self.log
+            .new(o!("rpc" => rpc, "peer" => peer.addr.to_string(), "hops" => hops))
+    }
+
+    /// The node's metrics in the Prometheus text format, for serving over HTTP.
+    pub fn render_metrics(&self) -> String {
+        let itemcount = self.query_engine.local_node.read().unwrap().meta.itemcount;
+        let pool_size = self.client_pool.lock().unwrap().len();
+        self.metrics
+            .render(&[("chord_items", "Items stored on this node.", itemcount as f64),
+                      ("chord_client_pool_size",
+                       "Open connections to other nodes.",
+                       pool_size as f64)])
     }
 
     fn client(&self, id: Id) -> FutureClient {
@@ -101,15 +339,376 @@ impl ChordServer {
         client_pool
             .entry(id)
             .or_insert_with(|| {
-                                //println!("^^^ {:?}", id.addr);
-                                let c = FutureClient::connect(id.addr, client::Options::default())
+                                debug!(self.log, "Connecting"; "peer" => id.addr.to_string());
+                                FutureClient::connect(id.addr, client::Options::default())
                                     .wait()
-                                    .unwrap();
-                                //println!("___ {:?}", id.addr);
-                                c
+                                    .unwrap()
                             })
             .clone()
     }
+
+    /// Like `client`, but reports a peer that cannot be reached instead of panicking.
+    fn try_client(&self, id: Id) -> io::Result<FutureClient> {
+        if let Some(client) = self.client_pool.lock().unwrap().get(&id) {
+            return Ok(client.clone());
+        }
+        debug!(self.log, "Connecting"; "peer" => id.addr.to_string());
+        let client = FutureClient::connect(id.addr, client::Options::default())
+            .wait()
+            .map_err(|e| {
+                         warn!(self.log, "Could not connect";
+                               "peer" => id.addr.to_string(),
+                               "error" => e.to_string());
+                         e
+                     })?;
+        self.client_pool.lock().unwrap().insert(id, client.clone());
+        Ok(client)
+    }
+
+    /// The node owning `key`, found by following successors from this one.
+    fn find_owner(&self, key: Key) -> Box<Future<Item = Id, Error = TimeoutErr<bool>>> {
+        let query = OwnerQuery { key };
+        match self.query_engine.owner(query) {
+            QueryResult::Answer(answer) => box future::ok(answer),
+            QueryResult::Node(node_id) => {
+                self.forward("owner", key, node_id, move |next| box next.owner(key))
+            }
+        }
+    }
+
+    /// Passes a call of `rpc` for `key`, which this node does not own, on to `next_id`,
+    /// the next node towards its owner. That node passes it on in turn until it reaches
+    /// the owner, one hop at a time.
+    fn forward<T, F>(&self,
+                     rpc: &'static str,
+                     key: Key,
+                     next_id: Id,
+                     send: F)
+                     -> Box<Future<Item = T, Error = TimeoutErr<bool>>>
+        where T: 'static,
+              F: FnOnce(&FutureClient) -> ClientFuture<T>
+    {
+        let next = match self.try_client(next_id) {
+            Ok(next) => next,
+            Err(_) => return box future::err(TimeoutErr::FutureErr(false)),
+        };
+        let log = self.forwarding(rpc, next_id, Some(key));
+        box self.timer
+                .timeout(send(&next).map_err(move |e| forward_failed(&log, e)),
+                         self.request_timeout)
+    }
+
+    /// What `get_many` answers: the local keys from this node, the rest from their
+    /// owners.
+    fn find_many(&self,
+                 keys: Vec<Key>)
+                 -> Box<Future<Item = Vec<(Key, KeyResult<Option<Definitions>>)>,
+                               Error = TimeoutErr<bool>>> {
+        let query = GetManyQuery { keys };
+        let BatchResult { answers, unowned } = self.query_engine.get_many(query);
+        let keys = unowned.into_iter().map(|key| (key, key)).collect();
+        box self.send_to_owners("get_many", keys, |client, keys| box client.get_many(keys))
+                .map(move |mut results| {
+                         results.extend(answers
+                                            .into_iter()
+                                            .map(|(key, answer)| (key, Ok(answer))));
+                         results
+                     })
+    }
+
+    /// What `references` answers, for RPCs that look rows up by a secondary key.
+    fn find_references(&self,
+                       index_key: Key)
+                       -> Box<Future<Item = Vec<Key>, Error = TimeoutErr<bool>>> {
+        let query = ReferencesQuery { index_key };
+        match self.query_engine.references(query) {
+            QueryResult::Answer(answer) => box future::ok(answer),
+            QueryResult::Node(node_id) => {
+                self.forward("references",
+                             index_key,
+                             node_id,
+                             move |next| box next.references(index_key))
+            }
+        }
+    }
+
+    /// Finds the owner of every key in `items` from one walk of the ring, then hands
+    /// each owner its items as a single batch through `send`. An owner that cannot be
+    /// found or reached fails only the keys it would have been sent, so every key gets a
+    /// result of its own.
+    fn send_to_owners<R, T, F>(&self,
+                               rpc: &'static str,
+                               items: Vec<(Key, R)>,
+                               send: F)
+                               -> Box<Future<Item = Vec<(Key, KeyResult<T>)>,
+                                             Error = TimeoutErr<bool>>>
+        where R: 'static,
+              T: 'static,
+              F: Fn(&FutureClient, Vec<R>) -> ClientFuture<Vec<(Key, KeyResult<T>)>> + 'static
+    {
+        if items.is_empty() {
+            return box future::ok(vec![]);
+        }
+        // One walk of the ring finds the owners of every key at once, where looking each
+        // key up would cost a round of hops per key.
+        let server = self.clone();
+        box self.find_topology().and_then(move |topology| {
+            let mut results = vec![];
+            let mut batches = Batches::new();
+            for (key, item) in items {
+                match topology.nodes.iter().find(|meta| meta.owns(key)) {
+                    Some(owner) => batches.add(owner.id, (key, item)),
+                    None => results.push((key, Err(TimeoutErr::FutureErr(false)))),
+                }
+            }
+            let sent = batches
+                .batches
+                .into_iter()
+                .map(|(owner, items)| server.send_batch(rpc, owner, items, &send))
+                .collect::<Vec<_>>();
+            future::join_all(sent).map(move |replies| {
+                                           for reply in replies {
+                                               results.extend(reply);
+                                           }
+                                           results
+                                       })
+        })
+    }
+
+    /// Sends one batch of `send_to_owners`, failing each of its keys if it fails.
+    fn send_batch<R, T, F>(&self,
+                           rpc: &'static str,
+                           owner: Id,
+                           items: Vec<(Key, R)>,
+                           send: &F)
+                           -> Box<Future<Item = Vec<(Key, KeyResult<T>)>,
+                                         Error = TimeoutErr<bool>>>
+        where R: 'static,
+              T: 'static,
+              F: Fn(&FutureClient, Vec<R>) -> ClientFuture<Vec<(Key, KeyResult<T>)>>
+    {
+        let keys = items.iter().map(|&(key, _)| key).collect::<Vec<_>>();
+        let failed = move |e: TimeoutErr<bool>| {
+            let results = keys.into_iter().map(|key| (key, Err(e.clone()))).collect::<Vec<_>>();
+            Ok::<_, TimeoutErr<bool>>(results)
+        };
+        let client = match self.try_client(owner) {
+            Ok(client) => client,
+            Err(_) => return box future::result(failed(TimeoutErr::FutureErr(false))),
+        };
+        let log = self.forwarding(rpc, owner, None);
+        let items = items.into_iter().map(|(_, item)| item).collect();
+        box self.timer
+                .timeout(send(&client, items).map_err(move |e| forward_failed(&log, e)),
+                         self.request_timeout)
+                .or_else(failed)
+    }
+
+    /// Sends `change` on towards the node owning its secondary key.
+    fn send_reference(&self,
+                      change: ReferenceChange<Key>)
+                      -> Box<Future<Item = (), Error = TimeoutErr<bool>>> {
+        let ReferenceChange {
+            index_key,
+            primary_key,
+            entry,
+        } = change;
+        let query = ReferenceQuery {
+            index_key: index_key,
+            primary_key: primary_key,
+            entry: entry.clone(),
+        };
+        match self.query_engine.reference(query) {
+            QueryResult::Answer(answer) => box future::ok(answer),
+            QueryResult::Node(node_id) => {
+                self.forward("reference",
+                             index_key,
+                             node_id,
+                             move |next| box next.reference(index_key, primary_key, entry))
+            }
+        }
+    }
+
+    /// Sends the reference changes queued by local writes to the nodes owning their
+    /// secondary keys. Those that cannot be sent go back on the queue for `sweeper` to
+    /// retry, so this never fails: the writes behind them have already been made.
+    fn flush_references(&self) -> Box<Future<Item = (), Error = TimeoutErr<bool>>> {
+        let changes = self.query_engine.take_reference_changes();
+        if changes.is_empty() {
+            return box future::ok(());
+        }
+        let sent = changes
+            .into_iter()
+            .map(|change| {
+                     let unsent = change.clone();
+                     self.send_reference(change)
+                         .then(move |result| {
+                                   Ok::<_, TimeoutErr<bool>>(result.err().map(|_| unsent))
+                               })
+                 })
+            .collect::<Vec<_>>();
+        let query_engine = self.query_engine.clone();
+        let log = self.log.clone();
+        box future::join_all(sent).map(move |unsent| {
+            let unsent = unsent.into_iter().filter_map(|change| change).collect::<Vec<_>>();
+            if !unsent.is_empty() {
+                warn!(log, "Could not send references, will retry"; "references" => unsent.len());
+                query_engine.requeue_reference_changes(unsent);
+            }
+        })
+    }
+
+    /// Asks the node `id`, `hops` nodes away, for its meta.
+    fn probe(&self,
+             id: Id,
+             hops: usize)
+             -> Box<Future<Item = NodeMeta<Id>, Error = TimeoutErr<bool>>> {
+        let node = match self.try_client(id) {
+            Ok(node) => node,
+            Err(_) => return box future::err(TimeoutErr::FutureErr(false)),
+        };
+        // Probes walk the ring rather than pass requests on, so they are not forwards.
+        let log = self.peer_logger("meta", id, hops);
+        let client_pool = self.client_pool.clone();
+        box self.timer
+                .timeout(node.meta().map_err(move |e| forward_failed(&log, e)),
+                         self.request_timeout)
+                .map_err(move |e| {
+                             // Reconnect next time, in case the node restarted.
+                             client_pool.lock().unwrap().remove(&id);
+                             e
+                         })
+    }
+
+    /// Walks the ring from `from` one node at a time, taking `step` from each node to the
+    /// next, until coming to a node in `seen` or to one not in a ring. Resolves to the
+    /// nodes visited in order, and to the node that did not answer, if one stopped it.
+    fn follow(&self,
+              from: Option<Id>,
+              seen: Vec<Id>,
+              step: fn(&NodeRelations<Id>) -> Id)
+              -> Box<Future<Item = (Vec<NodeMeta<Id>>, Option<Id>), Error = TimeoutErr<bool>>> {
+        let server = self.clone();
+        box future::loop_fn((from, seen, vec![]), move |(next, mut seen, mut metas)| {
+            let node_id = match next {
+                Some(node_id) if !seen.contains(&node_id) && seen.len() < MAX_RING_HOPS => node_id,
+                _ => return Either::A(future::ok(Loop::Break((metas, None)))),
+            };
+            Either::B(server
+                          .probe(node_id, metas.len() + 1)
+                          .then(move |meta| match meta {
+                                    Ok(meta) => {
+                                        seen.push(node_id);
+                                        let next = meta.relations.as_ref().map(step);
+                                        metas.push(meta);
+                                        Ok::<_, TimeoutErr<bool>>(Loop::Continue((next,
+                                                                                  seen,
+                                                                                  metas)))
+                                    }
+                                    Err(_) => Ok(Loop::Break((metas, Some(node_id)))),
+                                }))
+        })
+    }
+
+    /// What `topology` answers. Each node is asked on its own, so that one which does not
+    /// answer is reported rather than failing the whole walk.
+    fn find_topology(&self) -> Box<Future<Item = Topology, Error = TimeoutErr<bool>>> {
+        let origin = self.query_engine.local_node.read().unwrap().meta;
+        let successor = origin.relations.map(|relations| relations.successor_id);
+        let server = self.clone();
+        box self.follow(successor, vec![origin.id], |relations| relations.successor_id)
+                .and_then(move |(forward, stopped_at)| {
+            let mut nodes = vec![origin];
+            nodes.extend(forward);
+            let stopped_at = match stopped_at {
+                Some(stopped_at) => stopped_at,
+                None => {
+                    return Either::A(future::ok(Topology {
+                                                    nodes: nodes,
+                                                    unreachable: vec![],
+                                                }))
+                }
+            };
+            let mut seen = nodes.iter().map(|meta| meta.id).collect::<Vec<_>>();
+            seen.push(stopped_at);
+            let predecessor = origin.relations.map(|relations| relations.predecessor_id);
+            Either::B(server
+                          .follow(predecessor, seen, |relations| relations.predecessor_id)
+                          .map(move |(backward, also_stopped_at)| {
+                                   nodes.extend(backward.into_iter().rev());
+                                   let mut unreachable = vec![stopped_at];
+                                   unreachable.extend(also_stopped_at);
+                                   Topology {
+                                       nodes: nodes,
+                                       unreachable: unreachable,
+                                   }
+                               }))
+        })
+    }
+
+    /// Asks every node of `topology` at once through `ask`, other than this one, whose
+    /// answer is `local`, then merges the answers. Nodes that do not answer are reported
+    /// along with those the topology could not reach.
+    fn ask_ring<T, A, M>(&self,
+                         rpc: &'static str,
+                         topology: Topology,
+                         local: T,
+                         ask: A,
+                         merge: M)
+                         -> Box<Future<Item = RingAnswer<T>, Error = TimeoutErr<bool>>>
+        where T: 'static,
+              A: Fn(&FutureClient) -> ClientFuture<T>,
+              M: Fn(T, T) -> T + 'static
+    {
+        let id = self.query_engine.local_node.read().unwrap().meta.id;
+        let asked = topology
+            .nodes
+            .iter()
+            .map(|meta| meta.id)
+            .filter(|&node_id| node_id != id)
+            .map(|node_id| {
+                let node = match self.try_client(node_id) {
+                    Ok(node) => node,
+                    Err(_) => return Either::A(future::ok(Err(node_id))),
+                };
+                let log = self.forwarding(rpc, node_id, None);
+                Either::B(self.timer
+                              .timeout(ask(&node).map_err(move |e| forward_failed(&log, e)),
+                                       self.request_timeout)
+                              .then(move |answer| {
+                                        Ok::<_, TimeoutErr<bool>>(answer.map_err(|_| node_id))
+                                    }))
+            })
+            .collect::<Vec<_>>();
+        let mut unreachable = topology.unreachable;
+        box future::join_all(asked).map(move |answers| {
+            let mut merged = local;
+            for answer in answers {
+                match answer {
+                    Ok(answer) => merged = merge(merged, answer),
+                    Err(node_id) => unreachable.push(node_id),
+                }
+            }
+            RingAnswer {
+                answer: merged,
+                unreachable: unreachable,
+            }
+        })
+    }
+
+    /// Runs `QueryEngine::collect_garbage` every `interval`, and retries sending the
+    /// references that could not be sent before. Spawn this on the reactor alongside the
+    /// server.
+    pub fn sweeper(&self, interval: Duration) -> Box<Future<Item = (), Error = ()>> {
+        let server = self.clone();
+        box self.timer
+                .interval(interval)
+                .map_err(|_| ())
+                .for_each(move |_| {
+                              server.query_engine.collect_garbage();
+                              server.flush_references().then(|_| Ok(()))
+                          })
+    }
 }
 
 impl FutureService for ChordServer {
@@ -117,174 +716,535 @@ impl FutureService for ChordServer {
     type OwnerFut = Box<Future<Item = Id, Error = TimeoutErr<bool>>>;
     type RenameFut = Box<Future<Item = bool, Error = bool>>;
     type JoinFut = Box<Future<Item = bool, Error = bool>>;
-    type PrecedeFut = Box<Future<Item = PrecedeReply<Id, Definition>, Error = TimeoutErr<bool>>>;
+    type PrecedeFut = Box<Future<Item = PrecedeReply<Id, Definitions>, Error = TimeoutErr<bool>>>;
     type SucceedFut = Box<Future<Item = bool, Error = bool>>;
     type ExistsFut = Box<Future<Item = bool, Error = TimeoutErr<bool>>>;
     type GetFut = Box<Future<Item = Option<Definition>, Error = TimeoutErr<bool>>>;
+    type GetTopFut = Box<Future<Item = Vec<Definition>, Error = TimeoutErr<bool>>>;
     type SetFut = Box<Future<Item = (), Error = TimeoutErr<bool>>>;
+    type SetExpiringFut = Box<Future<Item = (), Error = TimeoutErr<bool>>>;
+    type GetManyFut = Box<Future<Item = Vec<(Key, KeyResult<Option<Definitions>>)>,
+                                 Error = TimeoutErr<bool>>>;
+    type SetManyFut = Box<Future<Item = Vec<(Key, KeyResult<()>)>, Error = TimeoutErr<bool>>>;
+    type ScanFut = Box<Future<Item = ScanPage<Key, Definitions>, Error = TimeoutErr<bool>>>;
+    type SearchFut = Box<Future<Item = RingAnswer<Vec<SearchHit<Key, Definitions>>>,
+                                Error = TimeoutErr<bool>>>;
+    type SearchLocalFut = Box<Future<Item = Vec<SearchHit<Key, Definitions>>,
+                                     Error = TimeoutErr<bool>>>;
+    type AutocompleteFut = Box<Future<Item = RingAnswer<Vec<String>>, Error = TimeoutErr<bool>>>;
+    type AutocompleteLocalFut = Box<Future<Item = Vec<String>, Error = TimeoutErr<bool>>>;
+    type ReferenceFut = Box<Future<Item = (), Error = TimeoutErr<bool>>>;
+    type ReferencesFut = Box<Future<Item = Vec<Key>, Error = TimeoutErr<bool>>>;
+    type ByAuthorFut = Box<Future<Item = Vec<Definition>, Error = TimeoutErr<bool>>>;
+    type ByUrbanIdFut = Box<Future<Item = Option<Definition>, Error = TimeoutErr<bool>>>;
+    type UpdateFut = Box<Future<Item = Option<Definition>, Error = TimeoutErr<bool>>>;
     type DeleteFut = Box<Future<Item = bool, Error = TimeoutErr<bool>>>;
+    type TopologyFut = Box<Future<Item = Topology, Error = TimeoutErr<bool>>>;
+    type MisplacedKeysFut = Box<Future<Item = Vec<Key>, Error = TimeoutErr<bool>>>;
+    type SetLogLevelFut = Box<Future<Item = bool, Error = bool>>;
+    type HealthFut = Box<Future<Item = Health, Error = bool>>;
+    type DrainFut = Box<Future<Item = bool, Error = bool>>;
 
     fn meta(&self) -> Self::MetaFut {
-        box match self.query_engine.meta() {
-                QueryResult::Answer(answer) => Either::A(future::ok(answer)),
-                QueryResult::Node(node_id) => {
-            Either::B(self.timer
-                          .timeout(self.client(node_id)
-                                       .meta()
-                                       .map_err(|e| {
-                                                    //println!("{:?}", e);
-                                                    TimeoutErr::FutureErr(false)
-                                                }),
-                                   Duration::from_secs(8)))
-        }
+        self.observe("meta", || {
+            box match self.query_engine.meta() {
+                    QueryResult::Answer(answer) => Either::A(future::ok(answer)),
+                    QueryResult::Node(node_id) => {
+                let log = self.forwarding("meta", node_id, None);
+                Either::B(self.timer
+                              .timeout(self.client(node_id)
+                                           .meta()
+                                           .map_err(move |e| forward_failed(&log, e)),
+                                       self.request_timeout))
             }
+                }
+        })
     }
 
     fn owner(&self, key: Key) -> Self::OwnerFut {
-        let query = OwnerQuery { key };
-        box match self.query_engine.owner(query) {
-                QueryResult::Answer(answer) => Either::A(future::ok(answer)),
-                QueryResult::Node(node_id) => {
-            Either::B(self.timer
-                          .timeout(self.client(node_id)
-                                       .owner(key)
-                                       .map_err(|e| {
-                                                    //println!("{:?}", e);
-                                                    TimeoutErr::FutureErr(false)
-                                                }),
-                                   Duration::from_secs(8)))
-        }
-            }
+        self.observe("owner", || self.find_owner(key))
     }
 
     fn rename(&self, new_node_id: Id) -> Self::RenameFut {
-        let mut node = self.query_engine.local_node.write().unwrap();
-        node.meta.id = new_node_id;
-        box future::ok(true)
+        self.observe("rename", || {
+            let mut node = self.query_engine.local_node.write().unwrap();
+            info!(self.log, "Renamed";
+                  "from" => node.meta.id.addr.to_string(),
+                  "to" => new_node_id.addr.to_string());
+            node.meta.id = new_node_id;
+            box future::ok(true)
+        })
     }
 
     fn join(&self, existing_node_id: Id) -> Self::JoinFut {
-        let mut node = self.query_engine.local_node.write().unwrap();
-        let precede_reply = self.client(existing_node_id)
-            .precede(node.meta.id)
-            .wait()
-            .unwrap();
-        node.apply_precede_reply(precede_reply);
-        box future::ok(true)
+        self.observe("join", || {
+            let id = self.query_engine.local_node.read().unwrap().meta.id;
+            let log = self.log.new(o!("peer" => existing_node_id.addr.to_string()));
+            if existing_node_id == id {
+                warn!(log, "Not joining through itself");
+                return box future::err(false);
+            }
+            self.joining();
+            let existing_node = match self.try_client(existing_node_id) {
+                Ok(existing_node) => existing_node,
+                Err(_) => return box future::err(false),
+            };
+            info!(log, "Joining");
+            // The node is left unlocked while waiting, as the ring may call back into it.
+            let server = self.clone();
+            let precede_log = log.clone();
+            box self.timer
+                    .timeout(existing_node
+                                 .precede(id)
+                                 .map_err(move |e| forward_failed(&precede_log, e)),
+                             self.request_timeout)
+                    .then(move |precede_reply| {
+                        let precede_reply = match precede_reply {
+                            Ok(precede_reply) => precede_reply,
+                            Err(_) => {
+                                // Reconnect next time, in case the peer restarted. Retrying
+                                // is safe even if the peer took the node in after all: see
+                                // `QueryEngine::precede`.
+                                server.client_pool.lock().unwrap().remove(&existing_node_id);
+                                return Err(false);
+                            }
+                        };
+                        info!(log, "Joined";
+                              "predecessor" => precede_reply.predecessor_id.addr.to_string(),
+                              "successor" => precede_reply.successor_id.addr.to_string(),
+                              "items" => precede_reply.transfer_items.len());
+                        let transfer = server.transfer();
+                        server
+                            .query_engine
+                            .local_node
+                            .write()
+                            .unwrap()
+                            .apply_precede_reply(precede_reply);
+                        drop(transfer);
+                        *server.joining.lock().unwrap() = false;
+                        Ok(true)
+                    })
+        })
     }
 
     fn precede(&self, predecessor_id: Id) -> Self::PrecedeFut {
-        let query = PrecedeQuery { id: predecessor_id };
-        box match self.query_engine.precede(query) {
-                QueryResult::Answer(answer) => {
-            if answer.predecessor_id != answer.successor_id {
-                self.client(answer.predecessor_id)
-                    .succeed(predecessor_id)
-                    .wait()
-                    .unwrap();
+        self.observe("precede", || {
+            let query = PrecedeQuery { id: predecessor_id };
+            box match self.query_engine.precede(query) {
+                    QueryResult::Answer(answer) => {
+                if answer.predecessor_id == answer.successor_id {
+                    return box future::ok(answer);
+                }
+                // The old predecessor must follow the new one before it is answered, or
+                // the new one would be left out of the ring. Failing here has the new
+                // predecessor retry, which tells the old one again. The keys it took over
+                // are in transfer until then.
+                let transfer = self.transfer();
+                let old_predecessor_id = answer.predecessor_id;
+                let old_predecessor = match self.try_client(old_predecessor_id) {
+                    Ok(old_predecessor) => old_predecessor,
+                    Err(_) => return box future::err(TimeoutErr::FutureErr(false)),
+                };
+                let log = self.forwarding("succeed", old_predecessor_id, None);
+                Either::A(self.timer
+                              .timeout(old_predecessor
+                                           .succeed(predecessor_id)
+                                           .map_err(move |e| forward_failed(&log, e)),
+                                       self.request_timeout)
+                              .map(move |_| {
+                                       drop(transfer);
+                                       answer
+                                   }))
             }
-            Either::A(future::ok(answer))
-        }
-                QueryResult::Node(node_id) => {
-            Either::B(self.timer
-                          .timeout(self.client(node_id)
-                                       .precede(predecessor_id)
-                                       .map_err(|e| {
-                                                    //println!("{:?}", e);
-                                                    TimeoutErr::FutureErr(false)
-                                                }),
-                                   Duration::from_secs(8)))
-        }
+                    QueryResult::Node(node_id) => {
+                let log = self.forwarding("precede", node_id, None);
+                Either::B(self.timer
+                              .timeout(self.client(node_id)
+                                           .precede(predecessor_id)
+                                           .map_err(move |e| forward_failed(&log, e)),
+                                       self.request_timeout))
             }
+                }
+        })
     }
 
     fn succeed(&self, successor_id: Id) -> Self::JoinFut {
-        let mut node = self.query_engine.local_node.write().unwrap();
-        box match node.meta.relations.as_mut() {
-                Some(ref mut relations) => {
-            relations.successor_id = successor_id;
-            future::ok(true)
-        }
-                None => future::err(false),
+        self.observe("succeed", || {
+            let mut node = self.query_engine.local_node.write().unwrap();
+            if node.set_successor(successor_id) {
+                info!(self.log, "New successor"; "successor" => successor_id.addr.to_string());
+                box future::ok(true)
+            } else {
+                box future::err(false)
             }
+        })
     }
 
     fn exists(&self, key: Key) -> Self::ExistsFut {
-        let query = ExistsQuery { key };
-        box match self.query_engine.exists(query) {
-                QueryResult::Answer(answer) => Either::A(future::ok(answer)),
-                QueryResult::Node(node_id) => {
-            Either::B(self.timer
-                          .timeout(self.client(node_id)
-                                       .exists(key)
-                                       .map_err(|e| {
-                                                    //println!("{:?}", e);
-                                                    TimeoutErr::FutureErr(false)
-                                                }),
-                                   Duration::from_secs(8)))
-        }
+        self.observe("exists", || {
+            let query = ExistsQuery { key };
+            box match self.query_engine.exists(query) {
+                    QueryResult::Answer(answer) => Either::A(future::ok(answer)),
+                    QueryResult::Node(node_id) => {
+                Either::B(self.forward("exists", key, node_id, move |next| box next.exists(key)))
             }
+                }
+        })
     }
 
     fn get(&self, key: Key) -> Self::GetFut {
-        let query = GetQuery { key };
-        box match self.query_engine.get(query) {
-                QueryResult::Answer(answer) => Either::A(future::ok(answer)),
-                QueryResult::Node(node_id) => {
-            Either::B(self.timer
-                          .timeout(self.client(node_id)
-                                       .get(key)
-                                       .map_err(|e| {
-                                                    //println!("{:?}", e);
-                                                    TimeoutErr::FutureErr(false)
-                                                }),
-                                   Duration::from_secs(8)))
-        }
+        self.observe("get", || {
+            let query = GetQuery { key };
+            box match self.query_engine.get(query) {
+                    QueryResult::Answer(answer) => {
+                Either::A(future::ok(answer.and_then(|definitions| definitions.best())))
             }
+                    QueryResult::Node(node_id) => {
+                Either::B(self.forward("get", key, node_id, move |next| box next.get(key)))
+            }
+                }
+        })
+    }
+
+    fn get_top(&self, key: Key, n: usize) -> Self::GetTopFut {
+        self.observe("get_top", || {
+            let query = GetQuery { key };
+            box match self.query_engine.get(query) {
+                    QueryResult::Answer(answer) => {
+                Either::A(future::ok(answer.map_or(vec![], |definitions| definitions.top(n))))
+            }
+                    QueryResult::Node(node_id) => {
+                Either::B(self.forward("get_top",
+                                       key,
+                                       node_id,
+                                       move |next| box next.get_top(key, n)))
+            }
+                }
+        })
     }
 
     fn set(&self, key: Key, value: Definition) -> Self::SetFut {
-        //println!("set {:?} on {:?}", key, self.meta().wait().unwrap().id.addr);
-        let query = SetQuery {
-            key: key,
-            value: value.clone(),
-        };
-        box match self.query_engine.set(query) {
-                QueryResult::Answer(answer) => Either::A(future::ok(answer)),
-                QueryResult::Node(node_id) => {
-            //println!("set {:?} forwarding to {:?}", key, node_id.addr);
-            Either::B(self.timer
-                          .timeout(self.client(node_id)
-                                       .set(key, value)
-                                       .map(move |v| {
-                                                //println!("set {:?} forwarded to {:?}",
-                                                //         key,
-                                                //         node_id.addr);
-                                                v
-                                            })
-                                       .map_err(|e| {
-                                                    //println!("{:?}", e);
-                                                    TimeoutErr::FutureErr(false)
-                                                }),
-                                   Duration::from_secs(8)))
-        }
+        self.observe("set", || {
+            let query = SetQuery {
+                key: key,
+                value: Definitions::from(value.clone()),
+                ttl: None,
+            };
+            box match self.query_engine.set(query) {
+                    QueryResult::Answer(answer) => {
+                Either::A(self.flush_references().map(move |_| answer))
+            }
+                    QueryResult::Node(node_id) => {
+                Either::B(self.forward("set", key, node_id, move |next| box next.set(key, value)))
             }
+                }
+        })
+    }
+
+    fn set_expiring(&self, key: Key, value: Definition, ttl: Duration) -> Self::SetExpiringFut {
+        self.observe("set_expiring", || {
+            let query = SetQuery {
+                key: key,
+                value: Definitions::from(value.clone()),
+                ttl: Some(ttl),
+            };
+            box match self.query_engine.set(query) {
+                    QueryResult::Answer(answer) => {
+                Either::A(self.flush_references().map(move |_| answer))
+            }
+                    QueryResult::Node(node_id) => {
+                Either::B(self.forward("set_expiring",
+                                       key,
+                                       node_id,
+                                       move |next| box next.set_expiring(key, value, ttl)))
+            }
+                }
+        })
+    }
+
+    fn get_many(&self, keys: Vec<Key>) -> Self::GetManyFut {
+        self.observe("get_many", || self.find_many(keys))
+    }
+
+    fn set_many(&self, items: Vec<(Key, Definitions, Option<Duration>)>) -> Self::SetManyFut {
+        self.observe("set_many", || {
+            let query = SetManyQuery { items };
+            let BatchResult { answers, unowned } = self.query_engine.set_many(query);
+            let items = unowned
+                .into_iter()
+                .map(|item| (item.0, item))
+                .collect();
+            let sent = self.send_to_owners("set_many", items, |client, items| {
+                box client.set_many(items)
+            });
+            box self.flush_references()
+                    .join(sent)
+                    .map(move |(_, mut results)| {
+                             results.extend(answers.into_iter().map(|key| (key, Ok(()))));
+                             results
+                         })
+        })
+    }
+
+    fn scan(&self, start: ScanBound<Key>, end_key: Key, limit: usize) -> Self::ScanFut {
+        self.observe("scan", || {
+            let query = ScanQuery {
+                start: start,
+                end: end_key,
+                limit: limit,
+            };
+            box match self.query_engine.scan(query) {
+                    QueryResult::Answer(answer) => Either::A(future::ok(answer)),
+                    QueryResult::Node(node_id) => {
+                Either::B(match self.try_client(node_id) {
+                              Ok(next) => {
+                    let log = self.forwarding("scan", node_id, None);
+                    Either::A(self.timer
+                                  .timeout(next.scan(start, end_key, limit)
+                                               .map_err(move |e| forward_failed(&log, e)),
+                                           self.request_timeout))
+                }
+                              Err(_) => Either::B(future::err(TimeoutErr::FutureErr(false))),
+                          })
+            }
+                }
+        })
+    }
+
+    fn search(&self, query: String, limit: usize) -> Self::SearchFut {
+        self.observe("search", || {
+            let local_hits = self.query_engine.search(SearchQuery {
+                                                          query: query.clone(),
+                                                          limit: limit,
+                                                      });
+            let server = self.clone();
+            box self.find_topology().and_then(move |topology| {
+                server.ask_ring("search_local",
+                                topology,
+                                local_hits,
+                                move |node| box node.search_local(query.clone(), limit),
+                                move |hits, more| merge_hits(hits, more, limit))
+            })
+        })
+    }
+
+    fn search_local(&self, query: String, limit: usize) -> Self::SearchLocalFut {
+        self.observe("search_local", || {
+            box future::ok(self.query_engine.search(SearchQuery {
+                                                        query: query,
+                                                        limit: limit,
+                                                    }))
+        })
+    }
+
+    fn autocomplete(&self, prefix: String, limit: usize) -> Self::AutocompleteFut {
+        self.observe("autocomplete", || {
+            let local_terms = self.query_engine.autocomplete(AutocompleteQuery {
+                                                                 prefix: prefix.clone(),
+                                                                 limit: limit,
+                                                             });
+            let server = self.clone();
+            box self.find_topology().and_then(move |topology| {
+                server.ask_ring("autocomplete_local",
+                                topology,
+                                local_terms,
+                                move |node| box node.autocomplete_local(prefix.clone(), limit),
+                                move |terms, more| merge_completions(terms, more, limit))
+            })
+        })
+    }
+
+    fn autocomplete_local(&self, prefix: String, limit: usize) -> Self::AutocompleteLocalFut {
+        self.observe("autocomplete_local", || {
+            box future::ok(self.query_engine.autocomplete(AutocompleteQuery {
+                                                              prefix: prefix,
+                                                              limit: limit,
+                                                          }))
+        })
+    }
+
+    fn reference(&self, index_key: Key, primary_key: Key, entry: Entry<()>) -> Self::ReferenceFut {
+        self.observe("reference", || {
+            self.send_reference(ReferenceChange {
+                                    index_key: index_key,
+                                    primary_key: primary_key,
+                                    entry: entry,
+                                })
+        })
+    }
+
+    fn references(&self, index_key: Key) -> Self::ReferencesFut {
+        self.observe("references", || self.find_references(index_key))
+    }
+
+    fn by_author(&self, author: String) -> Self::ByAuthorFut {
+        self.observe("by_author", || {
+            // References can briefly outlive a change of author, so the rows themselves
+            // are checked too.
+            let server = self.clone();
+            box self.find_references(author_key(&author))
+                    .and_then(move |keys| server.find_many(keys))
+                    .and_then(move |found| {
+                        // Leaving out rows that could not be fetched would pass off a
+                        // partial answer as the whole one.
+                        let mut definitions = vec![];
+                        for (_, result) in found {
+                            if let Some(found) = result? {
+                                definitions.extend(found
+                                                       .by_urban_id
+                                                       .into_iter()
+                                                       .map(|(_, definition)| definition)
+                                                       .filter(|definition| {
+                                                                   definition.author == author
+                                                               }));
+                            }
+                        }
+                        Ok::<_, TimeoutErr<bool>>(definitions)
+                    })
+        })
+    }
+
+    fn by_urban_id(&self, urban_id: u64) -> Self::ByUrbanIdFut {
+        self.observe("by_urban_id", || {
+            let server = self.clone();
+            box self.find_references(urban_id_key(urban_id))
+                    .and_then(move |keys| server.find_many(keys))
+                    .and_then(move |found| {
+                        // The definition may be in a row that could not be fetched, so
+                        // finding nothing only counts if every row was fetched.
+                        let mut failed = None;
+                        for (_, result) in found {
+                            match result {
+                                Ok(Some(definitions)) => {
+                                    if let Some(definition) = definitions.get(urban_id) {
+                                        return Ok(Some(definition.clone()));
+                                    }
+                                }
+                                Ok(None) => {}
+                                Err(e) => failed = Some(e),
+                            }
+                        }
+                        failed.map_or(Ok(None), Err)
+                    })
+        })
+    }
+
+    fn update(&self, key: Key, update: DefinitionUpdate) -> Self::UpdateFut {
+        self.observe("update", || {
+            let query = UpdateQuery {
+                key: key,
+                update: update.clone(),
+            };
+            box match self.query_engine.update(query) {
+                    QueryResult::Answer(answer) => {
+                let urban_id = update.urban_id();
+                let answer = answer.and_then(|definitions| definitions.get(urban_id).cloned());
+                Either::A(self.flush_references().map(move |_| answer))
+            }
+                    QueryResult::Node(node_id) => {
+                Either::B(self.forward("update",
+                                       key,
+                                       node_id,
+                                       move |next| box next.update(key, update)))
+            }
+                }
+        })
     }
 
     fn delete(&self, key: Key) -> Self::DeleteFut {
-        let query = DeleteQuery { key };
-        box match self.query_engine.delete(query) {
-                QueryResult::Answer(answer) => Either::A(future::ok(answer)),
-                QueryResult::Node(node_id) => {
-            Either::B(self.timer
-                          .timeout(self.client(node_id)
-                                       .delete(key)
-                                       .map_err(|e| {
-                                                    //println!("{:?}", e);
-                                                    TimeoutErr::FutureErr(false)
-                                                }),
-                                   Duration::from_secs(8)))
-        }
+        self.observe("delete", || {
+            let query = DeleteQuery { key };
+            box match self.query_engine.delete(query) {
+                    QueryResult::Answer(answer) => {
+                Either::A(self.flush_references().map(move |_| answer))
             }
+                    QueryResult::Node(node_id) => {
+                Either::B(self.forward("delete", key, node_id, move |next| box next.delete(key)))
+            }
+                }
+        })
+    }
+
+    fn topology(&self) -> Self::TopologyFut {
+        self.observe("topology", || self.find_topology())
+    }
+
+    fn misplaced_keys(&self) -> Self::MisplacedKeysFut {
+        self.observe("misplaced_keys", || {
+            box future::ok(self.query_engine.misplaced_keys())
+        })
+    }
+
+    fn set_log_level(&self, level: String) -> Self::SetLogLevelFut {
+        self.observe("set_log_level", || match level.parse::<Level>() {
+                         Ok(level) => {
+                             info!(self.log, "Log level changed"; "level" => level.as_str());
+                             self.log_level.set(level);
+                             box future::ok(true)
+                         }
+                         Err(_) => box future::err(false),
+                     })
+    }
+
+    fn health(&self) -> Self::HealthFut {
+        self.observe("health", || {
+            let (id, relations) = {
+                let node = self.query_engine.local_node.read().unwrap();
+                (node.meta.id, node.meta.relations)
+            };
+            // Without relations the node owns the whole keyspace, which is only right for
+            // a node standing alone. With them it is ready as long as its successor is.
+            let state = if *self.leaving.lock().unwrap() {
+                HealthState::Leaving
+            } else if relations.is_none() && *self.joining.lock().unwrap() {
+                HealthState::Joining
+            } else {
+                HealthState::Ready
+            };
+            let health = Health {
+                state,
+                relations,
+                successor_reachable: None,
+                transferring: *self.transfers.lock().unwrap() > 0,
+            };
+            let successor_id = match relations {
+                Some(relations) if state == HealthState::Ready &&
+                                   relations.successor_id != id => relations.successor_id,
+                _ => return box future::ok(health),
+            };
+            let unreachable = Health {
+                state: HealthState::Degraded,
+                successor_reachable: Some(false),
+                ..health
+            };
+            let successor = match self.try_client(successor_id) {
+                Ok(successor) => successor,
+                Err(_) => return box future::ok(unreachable),
+            };
+            let log = self.log.new(o!("peer" => successor_id.addr.to_string()));
+            let client_pool = self.client_pool.clone();
+            box self.timer
+                    .timeout(successor.meta().map_err(move |e| forward_failed(&log, e)),
+                             self.request_timeout)
+                    .then(move |meta| if meta.is_ok() {
+                              Ok::<_, bool>(Health {
+                                                successor_reachable: Some(true),
+                                                ..health
+                                            })
+                          } else {
+                              // Reconnect next time, in case the successor restarted.
+                              client_pool.lock().unwrap().remove(&successor_id);
+                              Ok(unreachable)
+                          })
+        })
+    }
+
+    fn drain(&self) -> Self::DrainFut {
+        self.observe("drain", || {
+            // There is no undoing this: see `HealthState::Leaving`.
+            info!(self.log, "Draining");
+            *self.leaving.lock().unwrap() = true;
+            box future::ok(true)
+        })
     }
 }
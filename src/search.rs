@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+
+/// Values that can be found by words appearing in their text, or by the start of the
+/// term they are filed under.
+pub trait Document {
+    fn term(&self) -> &str;
+    fn text(&self) -> Vec<&str>;
+}
+
+/// Lowercased alphanumeric words of `text`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// An inverted index from words to the keys whose documents contain them, with the
+/// number of times each word occurs, alongside a sorted index of lowercased terms for
+/// prefix lookups.
+#[derive(Clone, Debug)]
+pub struct SearchIndex<K>
+    where K: Eq + Hash + Copy
+{
+    postings: HashMap<String, HashMap<K, u32>>,
+    words: HashMap<K, Vec<String>>,
+    terms: BTreeMap<String, HashSet<K>>,
+    term_by_key: HashMap<K, String>,
+}
+
+impl<K> SearchIndex<K>
+    where K: Eq + Hash + Copy
+{
+    pub fn new() -> SearchIndex<K> {
+        SearchIndex {
+            postings: HashMap::new(),
+            words: HashMap::new(),
+            terms: BTreeMap::new(),
+            term_by_key: HashMap::new(),
+        }
+    }
+
+    /// Index `document` under `key`, replacing whatever was indexed there before.
+    pub fn insert<D>(&mut self, key: K, document: &D)
+        where D: Document
+    {
+        self.remove(key);
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for text in document.text() {
+            for word in tokenize(text) {
+                *counts.entry(word).or_insert(0) += 1;
+            }
+        }
+        let mut words = Vec::with_capacity(counts.len());
+        for (word, count) in counts {
+            self.postings
+                .entry(word.clone())
+                .or_insert_with(HashMap::new)
+                .insert(key, count);
+            words.push(word);
+        }
+        self.words.insert(key, words);
+
+        let term = document.term().to_lowercase();
+        self.terms
+            .entry(term.clone())
+            .or_insert_with(HashSet::new)
+            .insert(key);
+        self.term_by_key.insert(key, term);
+    }
+
+    pub fn remove(&mut self, key: K) {
+        if let Some(words) = self.words.remove(&key) {
+            for word in words {
+                let now_empty = match self.postings.get_mut(&word) {
+                    Some(keys) => {
+                        keys.remove(&key);
+                        keys.is_empty()
+                    }
+                    None => false,
+                };
+                if now_empty {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+        if let Some(term) = self.term_by_key.remove(&key) {
+            let now_empty = match self.terms.get_mut(&term) {
+                Some(keys) => {
+                    keys.remove(&key);
+                    keys.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.terms.remove(&term);
+            }
+        }
+    }
+
+    /// Keys whose documents contain any word of `query`, scored by the total number of
+    /// occurrences of those words. Unordered.
+    pub fn search(&self, query: &str) -> Vec<(K, u32)> {
+        let mut query_words = tokenize(query);
+        query_words.sort();
+        query_words.dedup();
+
+        let mut scores: HashMap<K, u32> = HashMap::new();
+        for word in query_words {
+            if let Some(keys) = self.postings.get(&word) {
+                for (&key, &count) in keys {
+                    *scores.entry(key).or_insert(0) += count;
+                }
+            }
+        }
+        scores.into_iter().collect()
+    }
+
+    /// Up to `limit` keys, one per distinct term, whose terms start with `prefix`, in term
+    /// order. Only keys passing `accept` are considered.
+    pub fn complete<F>(&self, prefix: &str, limit: usize, mut accept: F) -> Vec<K>
+        where F: FnMut(K) -> bool
+    {
+        let prefix = prefix.to_lowercase();
+        self.terms
+            .range(prefix.clone()..)
+            .take_while(|&(term, _)| term.starts_with(&prefix))
+            .filter_map(|(_, keys)| keys.iter().cloned().find(|&key| accept(key)))
+            .take(limit)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchHit<K, T> {
+    pub key: K,
+    pub score: u32,
+    pub value: T,
+}
+
+/// Combine ranked hits from several nodes, keeping the best `limit`.
+pub fn merge_hits<K, T>(mut hits: Vec<SearchHit<K, T>>,
+                        more: Vec<SearchHit<K, T>>,
+                        limit: usize)
+                        -> Vec<SearchHit<K, T>>
+    where K: Ord
+{
+    hits.extend(more);
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.key.cmp(&b.key)));
+    hits.truncate(limit);
+    hits
+}
+
+/// Combine sorted term completions from several nodes, keeping the first `limit`.
+pub fn merge_completions(mut terms: Vec<String>,
+                         more: Vec<String>,
+                         limit: usize)
+                         -> Vec<String> {
+    terms.extend(more);
+    terms.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()));
+    terms.dedup_by(|a, b| a.to_lowercase() == b.to_lowercase());
+    terms.truncate(limit);
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Doc {
+        term: &'static str,
+        text: &'static str,
+    }
+
+    impl Document for Doc {
+        fn term(&self) -> &str {
+            self.term
+        }
+
+        fn text(&self) -> Vec<&str> {
+            vec![self.text]
+        }
+    }
+
+    fn index(docs: &[(u32, &'static str, &'static str)]) -> SearchIndex<u32> {
+        let mut index = SearchIndex::new();
+        for &(key, term, text) in docs {
+            index.insert(key,
+                         &Doc {
+                              term: term,
+                              text: text,
+                          });
+        }
+        index
+    }
+
+    fn sorted(mut hits: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+        hits.sort();
+        hits
+    }
+
+    #[test]
+    fn tokenize_splits_on_punctuation_and_lowercases() {
+        assert_eq!(tokenize("Yeet, it's YEET!  x2"),
+                   vec!["yeet", "it", "s", "yeet", "x2"]);
+        assert!(tokenize(" -- ").is_empty());
+    }
+
+    #[test]
+    fn search_scores_keys_by_occurrences_of_any_query_word() {
+        let index = index(&[(1, "a", "cool cool story"), (2, "b", "Cool beans"), (3, "c", "nope")]);
+        assert_eq!(sorted(index.search("COOL")), vec![(1, 2), (2, 1)]);
+        assert_eq!(sorted(index.search("cool beans beans")), vec![(1, 2), (2, 2)]);
+        assert!(index.search("missing").is_empty());
+    }
+
+    #[test]
+    fn search_forgets_removed_and_replaced_documents() {
+        let mut index = index(&[(1, "a", "old words"), (2, "b", "old")]);
+        index.remove(2);
+        index.insert(1,
+                     &Doc {
+                          term: "a",
+                          text: "new words",
+                      });
+        assert!(index.search("old").is_empty());
+        assert_eq!(index.search("new"), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn merged_hits_are_ranked_by_score_then_key() {
+        let hit = |key: u32, score: u32| {
+            SearchHit {
+                key: key,
+                score: score,
+                value: (),
+            }
+        };
+        let merged = merge_hits(vec![hit(3, 1), hit(2, 5)], vec![hit(1, 1), hit(4, 9)], 3);
+        let ranked = merged.iter().map(|hit| hit.key).collect::<Vec<_>>();
+        assert_eq!(ranked, vec![4, 2, 1]);
+    }
+
+    #[test]
+    fn complete_lists_one_key_per_term_starting_with_the_prefix() {
+        let index = index(&[(1, "Yeet", ""), (2, "yeet", ""), (3, "yeeted", ""), (4, "yes", "")]);
+        let keys = index.complete("YEE", 10, |_| true);
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0] == 1 || keys[0] == 2);
+        assert_eq!(keys[1], 3);
+        assert_eq!(index.complete("yee", 1, |_| true).len(), 1);
+        assert!(index.complete("z", 10, |_| true).is_empty());
+    }
+
+    #[test]
+    fn complete_skips_keys_not_accepted() {
+        let index = index(&[(1, "yeet", ""), (2, "yeet", ""), (3, "yes", "")]);
+        assert_eq!(index.complete("ye", 10, |key| key != 1), vec![2, 3]);
+        assert_eq!(index.complete("ye", 10, |key| key == 3), vec![3]);
+    }
+
+    #[test]
+    fn merged_completions_are_sorted_and_distinct_regardless_of_case() {
+        let terms = |terms: &[&str]| terms.iter().map(|term| term.to_string()).collect();
+        let merged = merge_completions(terms(&["yeet", "yes"]), terms(&["Yeet", "ya", "yo"]), 3);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0], "ya");
+        assert_eq!(merged[1].to_lowercase(), "yeet");
+        assert_eq!(merged[2], "yes");
+    }
+}
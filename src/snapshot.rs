@@ -0,0 +1,260 @@
+use std::io::{self, SeekFrom};
+use std::collections::HashMap;
+use futures::{future, Future};
+use serde_json;
+use super::*;
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// One line of a snapshot file. A snapshot is a header, one record per term, and a
+/// trailer whose checksum covers every record, so truncation and corruption are both
+/// detected on restore. A record that expires keeps its expiry through a restore.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SnapshotLine {
+    Header { version: u32, created_at: Timestamp },
+    Record {
+        key: Key,
+        definitions: Definitions,
+        expires_at: Option<Timestamp>,
+        checksum: String,
+    },
+    Trailer { count: u64, checksum: String },
+}
+
+fn record_checksum(key: &Key,
+                   definitions: &Definitions,
+                   expires_at: Option<Timestamp>)
+                   -> Result<String, serde_json::Error> {
+    let mut m = sha1::Sha1::new();
+    m.update(serde_json::to_string(&(key, definitions, expires_at))?.as_bytes());
+    Ok(m.digest().to_string())
+}
+
+struct SnapshotWriter<W> {
+    writer: W,
+    count: u64,
+    digest: sha1::Sha1,
+}
+
+impl<W> SnapshotWriter<W>
+    where W: io::Write
+{
+    fn write_line(&mut self, line: &SnapshotLine) -> Result<(), ExportError> {
+        serde_json::to_writer(&mut self.writer, line)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn write_record(&mut self,
+                    key: Key,
+                    definitions: Definitions,
+                    expires_at: Option<Timestamp>)
+                    -> Result<(), ExportError> {
+        let checksum = record_checksum(&key, &definitions, expires_at)?;
+        self.digest.update(checksum.as_bytes());
+        self.count += 1;
+        self.write_line(&SnapshotLine::Record {
+                             key: key,
+                             definitions: definitions,
+                             expires_at: expires_at,
+                             checksum: checksum,
+                         })
+    }
+
+    fn finish(mut self) -> Result<(W, u64), ExportError> {
+        let trailer = SnapshotLine::Trailer {
+            count: self.count,
+            checksum: self.digest.digest().to_string(),
+        };
+        self.write_line(&trailer)?;
+        self.writer.flush()?;
+        Ok((self.writer, self.count))
+    }
+}
+
+/// Write every item in the ring to `writer` as a snapshot, walking it in pages of
+/// `page_size` terms. Resolves to the writer and the number of terms written.
+pub fn write_snapshot<W>(client: FutureClient,
+                         writer: W,
+                         page_size: usize)
+                         -> Box<Future<Item = (W, u64), Error = ExportError>>
+    where W: io::Write + 'static
+{
+    let mut snapshot = SnapshotWriter {
+        writer: writer,
+        count: 0,
+        digest: sha1::Sha1::new(),
+    };
+    let header = SnapshotLine::Header {
+        version: SNAPSHOT_VERSION,
+        created_at: timestamp(),
+    };
+    if let Err(e) = snapshot.write_line(&header) {
+        return box future::err(e);
+    }
+
+    box walk_ring(client, page_size, snapshot, |mut snapshot, page| {
+        let expiring = page.expiring.into_iter().collect::<HashMap<_, _>>();
+        for (key, definitions) in page.items {
+            let expires_at = expiring.get(&key).cloned();
+            snapshot.write_record(key, definitions, expires_at)?;
+        }
+        Ok(snapshot)
+    })
+            .and_then(|snapshot| snapshot.finish())
+}
+
+/// Reads a snapshot back as rows, one per definition. Records failing their checksum are
+/// rejected, as is a missing header and a missing or mismatched trailer.
+pub struct SnapshotRows<R> {
+    lines: io::Lines<R>,
+    line: u64,
+    header: bool,
+    count: u64,
+    digest: sha1::Sha1,
+    pending: Vec<Row>,
+    done: bool,
+}
+
+impl<R> SnapshotRows<R>
+    where R: io::BufRead
+{
+    pub fn new(reader: R) -> SnapshotRows<R> {
+        SnapshotRows {
+            lines: reader.lines(),
+            line: 0,
+            header: false,
+            count: 0,
+            digest: sha1::Sha1::new(),
+            pending: vec![],
+            done: false,
+        }
+    }
+
+    fn reject(&mut self, reason: String) -> Row {
+        Row::Rejected(Rejection {
+                          line: self.line,
+                          reason: reason,
+                      })
+    }
+
+    fn read_line(&mut self, text: &str) -> Option<Row> {
+        let line = match serde_json::from_str::<SnapshotLine>(text) {
+            Ok(line) => line,
+            Err(e) => return Some(self.reject(e.to_string())),
+        };
+        match line {
+            SnapshotLine::Header { version, .. } => {
+                if version != SNAPSHOT_VERSION {
+                    self.done = true;
+                    let reason = format!("Unsupported snapshot version {}.", version);
+                    return Some(self.reject(reason));
+                }
+                self.header = true;
+                None
+            }
+            _ if !self.header => {
+                self.done = true;
+                Some(self.reject("Snapshot has no header.".to_string()))
+            }
+            SnapshotLine::Record {
+                key,
+                definitions,
+                expires_at,
+                checksum,
+            } => {
+                match record_checksum(&key, &definitions, expires_at) {
+                    Ok(ref actual) if *actual == checksum => {}
+                    _ => return Some(self.reject("Record checksum mismatch.".to_string())),
+                }
+                self.digest.update(checksum.as_bytes());
+                self.count += 1;
+                let line = self.line;
+                self.pending = definitions
+                    .by_urban_id
+                    .into_iter()
+                    .map(|(_, definition)| {
+                             Row::Accepted {
+                                 line: line,
+                                 key: key,
+                                 definition: definition,
+                                 expires_at: expires_at,
+                             }
+                         })
+                    .collect();
+                self.pending.pop()
+            }
+            SnapshotLine::Trailer { count, checksum } => {
+                self.done = true;
+                if count != self.count || checksum != self.digest.digest().to_string() {
+                    return Some(self.reject("Snapshot trailer does not match its records."
+                                                .to_string()));
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<R> Iterator for SnapshotRows<R>
+    where R: io::BufRead
+{
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        loop {
+            if let Some(row) = self.pending.pop() {
+                return Some(row);
+            }
+            if self.done {
+                return None;
+            }
+            self.line += 1;
+            let row = match self.lines.next() {
+                Some(Ok(text)) => self.read_line(&text),
+                Some(Err(e)) => Some(self.reject(e.to_string())),
+                None => {
+                    self.done = true;
+                    Some(self.reject("Snapshot has no trailer, so may be truncated.".to_string()))
+                }
+            };
+            if row.is_some() {
+                return row;
+            }
+        }
+    }
+}
+
+/// Load a snapshot back into the ring through `client`'s `set_many`, expiring records
+/// with what is left of their TTL. The snapshot is read through once to check it, and
+/// nothing is loaded from one that fails its checks: the report then only holds what is
+/// wrong with it. Otherwise it is read through again to load it. See `import_rows`.
+pub fn restore_snapshot<R>(mut reader: R,
+                           client: FutureClient,
+                           batch_size: usize,
+                           concurrency: usize)
+                           -> Box<Future<Item = ImportReport, Error = ()>>
+    where R: io::BufRead + io::Seek + 'static
+{
+    let mut rejected = SnapshotRows::new(&mut reader)
+        .filter_map(|row| match row {
+                        Row::Rejected(rejection) => Some(rejection),
+                        Row::Accepted { .. } => None,
+                    })
+        .collect::<Vec<_>>();
+    if rejected.is_empty() {
+        if let Err(e) = reader.seek(SeekFrom::Start(0)) {
+            rejected.push(Rejection {
+                              line: 0,
+                              reason: format!("Could not read the snapshot again: {}", e),
+                          });
+        }
+    }
+    if !rejected.is_empty() {
+        return box future::ok(ImportReport {
+                                  rejected: rejected,
+                                  ..ImportReport::default()
+                              });
+    }
+    import_rows(SnapshotRows::new(reader), client, batch_size, concurrency)
+}
@@ -0,0 +1,82 @@
+use std::fmt::Write;
+use super::*;
+
+/// The ring as found by walking it from one node: the nodes that answered, starting from
+/// that one and in successor order, and the nodes that did not. A node that does not
+/// answer hides the nodes beyond it, so the walk comes round the other way through
+/// predecessors to find them.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Topology {
+    pub nodes: Vec<NodeMeta<Id>>,
+    pub unreachable: Vec<Id>,
+}
+
+/// A key as the 40 hex digits of the SHA-1 it came from.
+pub fn key_hex(key: &Key) -> String {
+    key.iter().map(|part| format!("{:08x}", part)).collect()
+}
+
+fn neighbours(meta: &NodeMeta<Id>) -> (String, String) {
+    match meta.relations {
+        Some(relations) => {
+            (relations.predecessor_id.addr.to_string(), relations.successor_id.addr.to_string())
+        }
+        None => ("-".to_string(), "-".to_string()),
+    }
+}
+
+/// One line per node as returned by the `topology` RPC, in ring order.
+pub fn topology_table(metas: &[NodeMeta<Id>]) -> String {
+    let mut table = String::new();
+    writeln!(table,
+             "{:<10} {:<22} {:<22} {:<22} {:>8}",
+             "key",
+             "addr",
+             "predecessor",
+             "successor",
+             "items")
+            .unwrap();
+    for meta in metas {
+        let (predecessor, successor) = neighbours(meta);
+        writeln!(table,
+                 "{:<10} {:<22} {:<22} {:<22} {:>8}",
+                 &key_hex(&meta.id.key)[..8],
+                 meta.id.addr.to_string(),
+                 predecessor,
+                 successor,
+                 meta.itemcount)
+                .unwrap();
+    }
+    table
+}
+
+/// The ring as a Graphviz digraph: solid edges to successors, dashed ones to
+/// predecessors.
+pub fn topology_dot(metas: &[NodeMeta<Id>]) -> String {
+    let mut dot = String::from("digraph ring {\n    node [shape=box];\n");
+    for meta in metas {
+        writeln!(dot,
+                 "    \"{}\" [label=\"{}\\n{}\\n{} items\"];",
+                 meta.id.addr,
+                 meta.id.addr,
+                 &key_hex(&meta.id.key)[..8],
+                 meta.itemcount)
+                .unwrap();
+    }
+    for meta in metas {
+        if let Some(relations) = meta.relations {
+            writeln!(dot,
+                     "    \"{}\" -> \"{}\";",
+                     meta.id.addr,
+                     relations.successor_id.addr)
+                    .unwrap();
+            writeln!(dot,
+                     "    \"{}\" -> \"{}\" [style=dashed];",
+                     meta.id.addr,
+                     relations.predecessor_id.addr)
+                    .unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
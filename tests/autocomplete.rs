@@ -0,0 +1,38 @@
+extern crate tarpc;
+extern crate chord;
+
+mod common;
+
+use std::net::SocketAddr;
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+#[test]
+fn autocomplete_finds_terms_on_every_node() {
+    let nodes = ring(4);
+    store(&nodes[0].1, 30);
+
+    let terms = nodes[3].1.autocomplete("term 1".to_string(), 100).wait().unwrap();
+    assert!(terms.unreachable.is_empty());
+    let mut expected = (0..30)
+        .map(|n| format!("term {}", n))
+        .filter(|term| term.starts_with("term 1"))
+        .collect::<Vec<_>>();
+    let mut found = terms.answer;
+    found.sort();
+    expected.sort();
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn autocomplete_answers_from_the_nodes_it_can_reach() {
+    let nodes = ring(3);
+    store(&nodes[0].1, 30);
+    let unreachable = Id::from("127.0.0.1:1".parse::<SocketAddr>().unwrap());
+    assert!(nodes[0].1.succeed(unreachable).wait().unwrap());
+
+    let terms = nodes[0].1.autocomplete("term".to_string(), 100).wait().unwrap();
+    assert_eq!(terms.unreachable, vec![unreachable]);
+    assert_eq!(terms.answer.len(), 30);
+}
@@ -0,0 +1,54 @@
+extern crate tarpc;
+extern crate chord;
+
+mod common;
+
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+fn items(count: u64) -> Vec<(Key, Definitions)> {
+    (0..count)
+        .map(|urban_id| {
+                 let definition = definition(urban_id);
+                 let key = term_key(&definition.canonical_term, &Normalization::default());
+                 (key, Definitions::from(definition))
+             })
+        .collect()
+}
+
+#[test]
+fn set_many_stores_each_key_on_its_owner() {
+    let nodes = ring(4);
+    let items = items(100);
+    let batch = items
+        .iter()
+        .map(|&(key, ref definitions)| (key, definitions.clone(), None))
+        .collect();
+    let results = nodes[0].1.set_many(batch).wait().unwrap();
+    assert_eq!(results.len(), 100);
+    assert!(results.iter().all(|&(_, ref result)| result.is_ok()));
+
+    for &(_, ref client) in &nodes {
+        assert_eq!(client.misplaced_keys().wait().unwrap(), vec![]);
+    }
+    let keys = items.iter().map(|&(key, _)| key).collect::<Vec<_>>();
+    let mut found = nodes[2].1.get_many(keys).wait().unwrap();
+    found.sort_by_key(|&(key, _)| key);
+    let mut expected = items;
+    expected.sort_by_key(|&(key, _)| key);
+    assert_eq!(found.len(), expected.len());
+    for (&(key, ref result), &(expected_key, ref definitions)) in found.iter().zip(&expected) {
+        assert_eq!(key, expected_key);
+        assert_eq!(result.as_ref().unwrap().as_ref(), Some(definitions));
+    }
+}
+
+#[test]
+fn get_many_answers_missing_keys_with_none() {
+    let nodes = ring(3);
+    let keys = items(10).into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+    let found = nodes[1].1.get_many(keys).wait().unwrap();
+    assert_eq!(found.len(), 10);
+    assert!(found.iter().all(|&(_, ref result)| result.as_ref().unwrap().is_none()));
+}
@@ -0,0 +1,76 @@
+//! Rings of real nodes on local ephemeral ports, for tests that go through the RPC layer.
+
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
+use tarpc::future::{client, server};
+use tarpc::future::client::ClientExt;
+use tarpc::futures::Future;
+use tarpc::tokio_core::reactor;
+use chord::*;
+
+/// Start a node on a reactor thread of its own, standing alone until told to join.
+pub fn start_node() -> (Id, FutureClient) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reactor = reactor::Core::new().unwrap();
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let chord_server = ChordServer::new(QueryEngine::new(Node::new(Id::from(addr))));
+        let (server_handle, server) = chord_server
+            .clone()
+            .listen(addr, &reactor.handle(), server::Options::default())
+            .unwrap();
+        let id = Id::from(server_handle.addr());
+        chord_server.rename(id).wait().unwrap();
+        tx.send(id).unwrap();
+        let _ = reactor.run(server);
+    });
+    let id = rx.recv().unwrap();
+    let client = FutureClient::connect(id.addr, client::Options::default())
+        .wait()
+        .unwrap();
+    (id, client)
+}
+
+/// Start `n` nodes and join them all into one ring through the first.
+pub fn ring(n: usize) -> Vec<(Id, FutureClient)> {
+    let nodes = (0..n).map(|_| start_node()).collect::<Vec<_>>();
+    for &(_, ref client) in &nodes[1..] {
+        assert!(client.join(nodes[0].0).wait().unwrap());
+    }
+    nodes
+}
+
+/// Whether some node owns both the top and the bottom of the keyspace, as the
+/// lowest-keyed node of any ring of more than one node does.
+pub fn has_wrapping_range(nodes: &[(Id, FutureClient)]) -> bool {
+    nodes
+        .iter()
+        .map(|&(_, ref client)| client.meta().wait().unwrap())
+        .any(|meta| {
+                 meta.relations
+                     .map_or(false, |relations| relations.predecessor_id.key > meta.id.key)
+             })
+}
+
+pub fn definition(urban_id: u64) -> Definition {
+    Definition {
+        urban_id: urban_id,
+        term: format!("term {}", urban_id),
+        canonical_term: format!("term {}", urban_id),
+        definition: format!("definition {}", urban_id),
+        author: format!("author {}", urban_id % 3),
+        example: String::new(),
+        thumbs_up: 0,
+        thumbs_down: 0,
+    }
+}
+
+/// Store definitions `0..count` through `client`, each under a term of its own.
+pub fn store(client: &FutureClient, count: u64) {
+    for urban_id in 0..count {
+        let definition = definition(urban_id);
+        let key = term_key(&definition.canonical_term, &Normalization::default());
+        client.set(key, definition).wait().unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+extern crate chord;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use chord::*;
+
+/// Write `text` to a file of its own named `name` in the temporary directory.
+fn config_file(name: &str, text: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("chord-config-test-{}", name));
+    File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+    path
+}
+
+#[test]
+fn fields_missing_from_a_file_take_their_defaults() {
+    let path = config_file("partial.toml", "listen = \"127.0.0.1:5000\"\njoin_retries = 2\n");
+    let config = Config::from_file(&path).unwrap();
+    assert_eq!(config.listen, "127.0.0.1:5000".parse().unwrap());
+    assert_eq!(config.join_retries, 2);
+    assert_eq!(config,
+               Config {
+                   listen: config.listen,
+                   join_retries: 2,
+                   ..Config::default()
+               });
+}
+
+#[test]
+fn files_ending_in_json_are_read_as_json() {
+    let path = config_file("node.json", "{\"bootstrap\": [\"10.0.0.1:4646\"]}");
+    let config = Config::from_file(&path).unwrap();
+    assert_eq!(config.bootstrap, vec!["10.0.0.1:4646".to_string()]);
+    assert!(Config::from_file(&config_file("node.toml", "{}")).is_err());
+}
+
+#[test]
+fn normalization_steps_missing_from_a_file_stay_on() {
+    let path = config_file("normalization.toml", "[normalization]\ntrim = false\n");
+    let normalization = Config::from_file(&path).unwrap().normalization;
+    assert_eq!(normalization,
+               Normalization {
+                   trim: false,
+                   ..Normalization::default()
+               });
+}
+
+#[test]
+fn invalid_settings_are_refused() {
+    let zero_grace = Config {
+        tombstone_grace_secs: 0,
+        ..Config::default()
+    };
+    assert!(zero_grace.validate().is_err());
+    let replicated = Config {
+        replication_factor: 3,
+        ..Config::default()
+    };
+    assert!(replicated.validate().is_err());
+    let path = config_file("bad-level.toml", "log_level = \"loud\"\n");
+    assert!(Config::from_file(&path).unwrap().validate().is_err());
+}
+
+// Environment variables are shared by every test in this file, so they are only set here.
+#[test]
+fn environment_overrides_the_file_which_overrides_defaults() {
+    let path = config_file("env.toml",
+                           "advertise = \"10.0.0.1:4646\"\nbootstrap = [\"10.0.0.2:4646\"]\n\
+                            request_timeout_secs = 3\njoin_retries = 2\n");
+    env::set_var("CHORD_REQUEST_TIMEOUT_SECS", "5");
+    env::set_var("CHORD_BOOTSTRAP", "10.0.0.3:4646, ,10.0.0.4:4646");
+    env::set_var("CHORD_ADVERTISE", "");
+    env::set_var("CHORD_NORMALIZATION", "case_fold");
+    let config = Config::load(Some(path.as_path()));
+    env::set_var("CHORD_JOIN_RETRIES", "lots");
+    let invalid = Config::load(Some(path.as_path()));
+    for name in &["CHORD_REQUEST_TIMEOUT_SECS",
+                  "CHORD_BOOTSTRAP",
+                  "CHORD_ADVERTISE",
+                  "CHORD_NORMALIZATION",
+                  "CHORD_JOIN_RETRIES"] {
+        env::remove_var(name);
+    }
+
+    let config = config.unwrap();
+    assert_eq!(config.request_timeout_secs, 5);
+    assert_eq!(config.bootstrap,
+               vec!["10.0.0.3:4646".to_string(), "10.0.0.4:4646".to_string()]);
+    assert_eq!(config.advertise, None);
+    assert_eq!(config.join_retries, 2);
+    assert_eq!(config.normalization,
+               Normalization {
+                   case_fold: true,
+                   ..Normalization::none()
+               });
+    assert_eq!(config.sweep_interval_secs, Config::default().sweep_interval_secs);
+    match invalid {
+        Err(ConfigError::Invalid(_)) => {}
+        other => panic!("Expected an invalid CHORD_JOIN_RETRIES, got {:?}", other),
+    }
+    assert_eq!(Config::load(Some(path.as_path())).unwrap().request_timeout_secs, 3);
+}
@@ -0,0 +1,111 @@
+extern crate tarpc;
+extern crate chord;
+extern crate serde_json;
+
+mod common;
+
+use std::io::Cursor;
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+#[test]
+fn export_writes_every_definition_once_when_a_range_wraps() {
+    let nodes = ring(4);
+    assert!(has_wrapping_range(&nodes));
+    let client = &nodes[0].1;
+    store(client, 200);
+
+    for &page_size in &[1, 7, 1000] {
+        let (output, count) = export_json(client.clone(), Vec::new(), JsonFormat::Ndjson, page_size)
+            .wait()
+            .unwrap();
+        let mut urban_ids = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Definition>(line).unwrap().urban_id)
+            .collect::<Vec<_>>();
+        urban_ids.sort();
+        assert_eq!(count, 200, "page size {}", page_size);
+        assert_eq!(urban_ids, (0..200).collect::<Vec<_>>(), "page size {}", page_size);
+    }
+}
+
+#[test]
+fn exported_array_reads_back() {
+    let nodes = ring(3);
+    let client = &nodes[0].1;
+    store(client, 20);
+
+    let (output, count) = export_json(client.clone(), Vec::new(), JsonFormat::Array, 3)
+        .wait()
+        .unwrap();
+    let definitions = serde_json::from_slice::<Vec<Definition>>(&output).unwrap();
+    assert_eq!(count, 20);
+    assert_eq!(definitions.len(), 20);
+}
+
+fn lines(rows: &[Row]) -> (Vec<u64>, Vec<u64>) {
+    let mut accepted = vec![];
+    let mut rejected = vec![];
+    for row in rows {
+        match *row {
+            Row::Accepted { line, .. } => accepted.push(line),
+            Row::Rejected(ref rejection) => rejected.push(rejection.line),
+        }
+    }
+    (accepted, rejected)
+}
+
+#[test]
+fn ndjson_rows_skip_blank_lines_and_reject_bad_ones_by_line() {
+    let good = serde_json::to_string(&definition(1)).unwrap();
+    let mut empty = definition(2);
+    empty.definition = String::new();
+    let text = format!("{}\n\n{{\"defid\": 3}}\nnot json\n{}\n  \n{}\n",
+                       good,
+                       serde_json::to_string(&empty).unwrap(),
+                       good);
+    let rows = NdjsonRows::new(Cursor::new(text), Normalization::default()).collect::<Vec<_>>();
+    assert_eq!(lines(&rows), (vec![1, 7], vec![3, 4, 5]));
+}
+
+#[test]
+fn json_array_rows_number_rejections_by_position() {
+    let text = format!("[{}, {{\"defid\": 3}}, {}]",
+                       serde_json::to_string(&definition(1)).unwrap(),
+                       serde_json::to_string(&definition(2)).unwrap());
+    let rows = json_array_rows(Cursor::new(text), &Normalization::default()).unwrap();
+    assert_eq!(lines(&rows), (vec![1, 3], vec![2]));
+    assert!(json_array_rows(Cursor::new("{}"), &Normalization::default()).is_err());
+}
+
+#[test]
+fn exported_ndjson_imports_into_another_ring() {
+    let source = ring(2);
+    store(&source[0].1, 30);
+    let (output, _) = export_json(source[0].1.clone(), Vec::new(), JsonFormat::Ndjson, 7)
+        .wait()
+        .unwrap();
+
+    let target = ring(3);
+    let client = &target[2].1;
+    let report = import_json(Cursor::new(output),
+                             JsonFormat::Ndjson,
+                             client.clone(),
+                             Normalization::default(),
+                             4,
+                             2)
+            .wait()
+            .unwrap();
+    assert_eq!(report.imported, 30);
+    assert!(report.rejected.is_empty());
+    for urban_id in 0..30 {
+        let term = definition(urban_id).canonical_term;
+        let found = client
+            .get_by_term(&term, &Normalization::default())
+            .wait()
+            .unwrap();
+        assert_eq!(found, Some(definition(urban_id)));
+    }
+}
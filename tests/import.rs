@@ -0,0 +1,128 @@
+extern crate tarpc;
+extern crate chord;
+
+mod common;
+
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+#[test]
+fn checkpoint_holds_before_the_first_row_not_stored() {
+    let nodes = ring(2);
+    let meta = nodes[0].1.meta().wait().unwrap();
+    // Everything the first node does not own itself now goes nowhere.
+    let unreachable = Id::from("127.0.0.1:1".parse::<SocketAddr>().unwrap());
+    assert!(nodes[0].1.succeed(unreachable).wait().unwrap());
+
+    let rows = (1..21)
+        .map(|line| {
+                 let definition = definition(line);
+                 Row::Accepted {
+                     line: line,
+                     key: term_key(&definition.canonical_term, &Normalization::default()),
+                     definition: definition,
+                     expires_at: None,
+                 }
+             })
+        .collect::<Vec<_>>();
+    let unstored = rows.iter()
+        .filter_map(|row| match *row {
+                        Row::Accepted { line, key, .. } if !meta.owns(key) => Some(line),
+                        _ => None,
+                    })
+        .collect::<Vec<_>>();
+    assert!(!unstored.is_empty() && unstored.len() < rows.len());
+
+    let checkpoints = Arc::new(Mutex::new(vec![]));
+    let options = LoadOptions {
+        batch_size: 1,
+        concurrency: 1,
+        ..LoadOptions::default()
+    };
+    let recorded = checkpoints.clone();
+    let report = load_rows(rows.into_iter(),
+                           nodes[0].1.clone(),
+                           &options,
+                           move |line| recorded.lock().unwrap().push(line))
+            .wait()
+            .unwrap();
+
+    assert_eq!(report.imported, 20 - unstored.len());
+    assert!(report.rejected.is_empty());
+    assert_eq!(report.unstored
+                   .iter()
+                   .map(|rejection| rejection.line)
+                   .collect::<Vec<_>>(),
+               unstored);
+    let checkpoints = checkpoints.lock().unwrap();
+    assert_eq!(checkpoints.last().cloned().unwrap_or(0), unstored[0] - 1);
+}
+
+const CSV: &'static str = "defid,word,entry,definition,author,example,thumbs_up,thumbs_down
+1,Yeet,yeet,To throw,a,Yeet it,5,1
+2,Bad,bad,Votes are not numbers,a,,many,0
+3,Empty,empty,,a,,0,0
+4,Short,short
+5,Fine,fine,Good enough,b,,1,0
+";
+
+fn rejected_lines(rejections: &[Rejection]) -> Vec<u64> {
+    rejections.iter().map(|rejection| rejection.line).collect()
+}
+
+#[test]
+fn csv_rows_reject_bad_rows_by_line_and_carry_on() {
+    let rows = CsvRows::new(Cursor::new(CSV), Normalization::default())
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(rows.iter().map(|row| row.line()).collect::<Vec<_>>(),
+               vec![2, 3, 4, 5, 6]);
+    let accepted = rows.iter()
+        .filter_map(|row| match *row {
+                        Row::Accepted { ref definition, key, .. } => Some((definition, key)),
+                        Row::Rejected(_) => None,
+                    })
+        .collect::<Vec<_>>();
+    assert_eq!(accepted.len(), 2);
+    assert_eq!(accepted[0].0.urban_id, 1);
+    assert_eq!(accepted[0].1, term_key("YEET", &Normalization::default()));
+    assert_eq!(accepted[1].0.urban_id, 5);
+}
+
+#[test]
+fn import_csv_stores_valid_rows_and_reports_the_rest() {
+    let nodes = ring(2);
+    let client = &nodes[1].1;
+    let report = import_csv(Cursor::new(CSV), client.clone(), Normalization::default(), 2, 2)
+        .wait()
+        .unwrap();
+    assert_eq!(report.imported, 2);
+    assert_eq!(rejected_lines(&report.rejected), vec![3, 4, 5]);
+    assert!(report.unstored.is_empty());
+
+    let yeet = nodes[0]
+        .1
+        .get_by_term(" yeet", &Normalization::default())
+        .wait()
+        .unwrap();
+    assert_eq!(yeet.unwrap().thumbs_up, 5);
+}
+
+#[test]
+fn resuming_still_reports_bad_rows_after_the_checkpoint() {
+    let nodes = ring(2);
+    let rows = CsvRows::new(Cursor::new(CSV), Normalization::default()).unwrap();
+    let options = LoadOptions {
+        resume_after: 3,
+        ..LoadOptions::default()
+    };
+    let report = load_rows(rows, nodes[0].1.clone(), &options, |_| {})
+        .wait()
+        .unwrap();
+    assert_eq!(report.imported, 1);
+    assert_eq!(rejected_lines(&report.rejected), vec![4, 5]);
+}
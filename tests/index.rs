@@ -0,0 +1,37 @@
+extern crate tarpc;
+extern crate chord;
+
+mod common;
+
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+#[test]
+fn by_author_finds_definitions_stored_anywhere_in_the_ring() {
+    let nodes = ring(3);
+    store(&nodes[0].1, 30);
+
+    let mut urban_ids = nodes[1]
+        .1
+        .by_author("author 1".to_string())
+        .wait()
+        .unwrap()
+        .into_iter()
+        .map(|definition| definition.urban_id)
+        .collect::<Vec<_>>();
+    urban_ids.sort();
+    assert_eq!(urban_ids, (0..30).filter(|n| n % 3 == 1).collect::<Vec<_>>());
+}
+
+#[test]
+fn by_urban_id_finds_definitions_stored_anywhere_in_the_ring() {
+    let nodes = ring(3);
+    store(&nodes[0].1, 30);
+
+    for (urban_id, &(_, ref client)) in (0..30).zip(nodes.iter().cycle()) {
+        let found = client.by_urban_id(urban_id).wait().unwrap();
+        assert_eq!(found, Some(definition(urban_id)));
+    }
+    assert_eq!(nodes[2].1.by_urban_id(30).wait().unwrap(), None);
+}
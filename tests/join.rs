@@ -0,0 +1,118 @@
+extern crate tarpc;
+extern crate chord;
+
+mod common;
+
+use std::net::SocketAddr;
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+/// Follow successors from the first node, checking that each takes the one before it as
+/// its predecessor, and that every node is visited once before coming back around.
+fn assert_linked(nodes: &[(Id, FutureClient)]) {
+    let client = |id: Id| &nodes.iter().find(|&&(node_id, _)| node_id == id).unwrap().1;
+    let mut visited = vec![];
+    let mut id = nodes[0].0;
+    loop {
+        let meta = client(id).meta().wait().unwrap();
+        let relations = meta.relations.unwrap();
+        let successor = client(relations.successor_id).meta().wait().unwrap();
+        assert_eq!(successor.relations.unwrap().predecessor_id, id);
+        visited.push(id);
+        id = relations.successor_id;
+        if id == nodes[0].0 {
+            break;
+        }
+        assert!(visited.len() < nodes.len(), "Ring does not close: {:?}", visited);
+    }
+    assert_eq!(visited.len(), nodes.len());
+}
+
+#[test]
+fn nodes_joining_one_by_one_form_a_ring() {
+    let nodes = ring(5);
+    assert_linked(&nodes);
+}
+
+#[test]
+fn joining_through_itself_fails() {
+    let (id, client) = start_node();
+    assert!(client.join(id).wait().is_err());
+}
+
+#[test]
+fn joining_again_leaves_the_ring_intact() {
+    // As when the reply to a join is lost and the node tries again.
+    for n in 2..5 {
+        let nodes = ring(n);
+        assert!(nodes[n - 1].1.join(nodes[0].0).wait().unwrap());
+        assert_linked(&nodes);
+    }
+}
+
+#[test]
+fn nodes_are_ready_and_not_transferring_once_joined() {
+    for (_, client) in ring(3) {
+        let health = client.health().wait().unwrap();
+        assert!(health.is_ready());
+        assert!(!health.transferring);
+    }
+}
+
+#[test]
+fn a_node_waiting_to_join_is_not_ready() {
+    let (_, client) = start_node();
+    assert!(client.health().wait().unwrap().is_ready());
+    let unreachable = Id::from("127.0.0.1:1".parse::<SocketAddr>().unwrap());
+    assert!(client.join(unreachable).wait().is_err());
+    assert_eq!(client.health().wait().unwrap().state, HealthState::Joining);
+}
+
+#[test]
+fn a_node_whose_successor_is_gone_is_degraded() {
+    let nodes = ring(2);
+    let unreachable = Id::from("127.0.0.1:1".parse::<SocketAddr>().unwrap());
+    assert!(nodes[0].1.succeed(unreachable).wait().unwrap());
+    let health = nodes[0].1.health().wait().unwrap();
+    assert_eq!(health.state, HealthState::Degraded);
+    assert_eq!(health.successor_reachable, Some(false));
+}
+
+#[test]
+fn a_drained_node_stays_unready() {
+    let (_, client) = start_node();
+    client.drain().wait().unwrap();
+    client.drain().wait().unwrap();
+    assert_eq!(client.health().wait().unwrap().state, HealthState::Leaving);
+}
+
+#[test]
+fn joining_a_ring_with_data_moves_keys_without_copying_them() {
+    let mut nodes = ring(2);
+    store(&nodes[0].1, 100);
+    for _ in 0..2 {
+        let node = start_node();
+        assert!(node.1.join(nodes[0].0).wait().unwrap());
+        nodes.push(node);
+    }
+    assert_linked(&nodes);
+
+    let topology = nodes[0].1.topology().wait().unwrap();
+    let misplaced = nodes
+        .iter()
+        .map(|&(id, ref client)| (id, client.misplaced_keys().wait().unwrap()))
+        .collect();
+    assert_eq!(check_ring(&topology, &misplaced), vec![]);
+    let itemcount = topology.nodes.iter().map(|meta| meta.itemcount).sum::<usize>();
+    assert_eq!(itemcount, 100);
+    for urban_id in 0..100 {
+        let term = definition(urban_id).canonical_term;
+        let found = nodes[3]
+            .1
+            .get_by_term(&term, &Normalization::default())
+            .wait()
+            .unwrap();
+        assert_eq!(found, Some(definition(urban_id)));
+    }
+}
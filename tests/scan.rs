@@ -0,0 +1,55 @@
+extern crate tarpc;
+extern crate chord;
+
+mod common;
+
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+/// Scan from `start` to `end` through `client`, `limit` items a page, following
+/// continuation tokens to the end.
+fn scan_keys(client: &FutureClient, start: Key, end: Key, limit: usize) -> Vec<Key> {
+    let mut keys = vec![];
+    let mut next = Some(ScanBound::Included(start));
+    while let Some(start) = next {
+        let page = client.scan(start, end, limit).wait().unwrap();
+        assert!(page.items.len() <= limit);
+        keys.extend(page.items.into_iter().map(|(key, _)| key));
+        next = page.next;
+    }
+    keys
+}
+
+fn stored_keys(count: u64) -> Vec<Key> {
+    let mut keys = (0..count)
+        .map(|urban_id| term_key(&definition(urban_id).canonical_term, &Normalization::default()))
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys
+}
+
+#[test]
+fn scan_pages_through_every_key_in_order() {
+    let nodes = ring(3);
+    let client = &nodes[0].1;
+    store(client, 50);
+
+    for &limit in &[1, 4, 50, 100] {
+        assert_eq!(scan_keys(client, MIN_KEY, MAX_KEY, limit),
+                   stored_keys(50),
+                   "limit {}",
+                   limit);
+    }
+}
+
+#[test]
+fn scan_stays_within_its_range() {
+    let nodes = ring(3);
+    let client = &nodes[1].1;
+    store(client, 50);
+
+    let keys = stored_keys(50);
+    let (start, end) = (keys[10], keys[39]);
+    assert_eq!(scan_keys(client, start, end, 7), &keys[10..40]);
+}
@@ -0,0 +1,42 @@
+extern crate tarpc;
+extern crate chord;
+
+mod common;
+
+use std::net::SocketAddr;
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+fn urban_ids(hits: &[SearchHit<Key, Definitions>]) -> Vec<u64> {
+    let mut urban_ids = hits.iter()
+        .flat_map(|hit| hit.value.by_urban_id.keys().cloned())
+        .collect::<Vec<_>>();
+    urban_ids.sort();
+    urban_ids
+}
+
+#[test]
+fn search_finds_definitions_on_every_node() {
+    let nodes = ring(4);
+    store(&nodes[0].1, 30);
+
+    let hits = nodes[1].1.search("definition".to_string(), 100).wait().unwrap();
+    assert!(hits.unreachable.is_empty());
+    assert_eq!(urban_ids(&hits.answer), (0..30).collect::<Vec<_>>());
+
+    let hits = nodes[2].1.search("definition".to_string(), 5).wait().unwrap();
+    assert_eq!(hits.answer.len(), 5);
+}
+
+#[test]
+fn search_answers_from_the_nodes_it_can_reach() {
+    let nodes = ring(4);
+    store(&nodes[0].1, 30);
+    let unreachable = Id::from("127.0.0.1:1".parse::<SocketAddr>().unwrap());
+    assert!(nodes[0].1.succeed(unreachable).wait().unwrap());
+
+    let hits = nodes[0].1.search("definition".to_string(), 100).wait().unwrap();
+    assert_eq!(hits.unreachable, vec![unreachable]);
+    assert_eq!(urban_ids(&hits.answer), (0..30).collect::<Vec<_>>());
+}
@@ -0,0 +1,209 @@
+extern crate tarpc;
+extern crate chord;
+extern crate serde_json;
+
+mod common;
+
+use std::io::Cursor;
+use std::time::Duration;
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+fn exported_urban_ids(client: &FutureClient) -> Vec<u64> {
+    let (output, _) = export_json(client.clone(), Vec::new(), JsonFormat::Ndjson, 50)
+        .wait()
+        .unwrap();
+    let mut urban_ids = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Definition>(line).unwrap().urban_id)
+        .collect::<Vec<_>>();
+    urban_ids.sort();
+    urban_ids
+}
+
+#[test]
+fn snapshot_records_every_key_once_when_a_range_wraps() {
+    let nodes = ring(4);
+    assert!(has_wrapping_range(&nodes));
+    let client = &nodes[0].1;
+    store(client, 200);
+
+    for &page_size in &[1, 7, 1000] {
+        let (output, count) = write_snapshot(client.clone(), Vec::new(), page_size)
+            .wait()
+            .unwrap();
+        let mut keys = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line).unwrap() {
+                            SnapshotLine::Record { key, .. } => Some(key),
+                            _ => None,
+                        })
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(count, 200, "page size {}", page_size);
+        assert_eq!(keys.len(), 200, "page size {}", page_size);
+    }
+}
+
+#[test]
+fn snapshot_restores_into_another_ring() {
+    let source = ring(3);
+    assert!(has_wrapping_range(&source));
+    store(&source[0].1, 100);
+    let (snapshot, _) = write_snapshot(source[0].1.clone(), Vec::new(), 9)
+        .wait()
+        .unwrap();
+
+    let target = ring(2);
+    let report = restore_snapshot(Cursor::new(snapshot), target[1].1.clone(), 10, 2)
+        .wait()
+        .unwrap();
+    assert_eq!(report.imported, 100);
+    assert_eq!(exported_urban_ids(&target[0].1), (0..100).collect::<Vec<_>>());
+}
+
+/// A snapshot of a ring holding definitions `0..count`, as lines.
+fn snapshot_lines(count: u64) -> Vec<String> {
+    let nodes = ring(2);
+    store(&nodes[0].1, count);
+    let (snapshot, _) = write_snapshot(nodes[0].1.clone(), Vec::new(), 10)
+        .wait()
+        .unwrap();
+    String::from_utf8(snapshot)
+        .unwrap()
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Restore `lines` into a ring of its own, returning the report and what the ring holds.
+fn restore_lines(lines: &[String]) -> (ImportReport, Vec<u64>) {
+    let target = ring(1);
+    let mut snapshot = lines.join("\n");
+    snapshot.push('\n');
+    let report = restore_snapshot(Cursor::new(snapshot.into_bytes()), target[0].1.clone(), 10, 2)
+        .wait()
+        .unwrap();
+    (report, exported_urban_ids(&target[0].1))
+}
+
+fn rejections(lines: &[String]) -> Vec<Rejection> {
+    let mut snapshot = lines.join("\n");
+    snapshot.push('\n');
+    SnapshotRows::new(Cursor::new(snapshot.into_bytes()))
+        .filter_map(|row| match row {
+                        Row::Rejected(rejection) => Some(rejection),
+                        Row::Accepted { .. } => None,
+                    })
+        .collect()
+}
+
+#[test]
+fn snapshot_rows_read_back_every_definition() {
+    let lines = snapshot_lines(25);
+    assert_eq!(lines.len(), 27);
+    let mut snapshot = lines.join("\n");
+    snapshot.push('\n');
+    let mut urban_ids = SnapshotRows::new(Cursor::new(snapshot.into_bytes()))
+        .map(|row| match row {
+                 Row::Accepted { definition, .. } => definition.urban_id,
+                 Row::Rejected(rejection) => panic!("Rejected {:?}.", rejection),
+             })
+        .collect::<Vec<_>>();
+    urban_ids.sort();
+    assert_eq!(urban_ids, (0..25).collect::<Vec<_>>());
+}
+
+#[test]
+fn snapshot_without_a_header_is_rejected() {
+    let lines = snapshot_lines(5);
+    let rejected = rejections(&lines[1..]);
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].line, 1);
+    assert_eq!(rejected[0].reason, "Snapshot has no header.");
+}
+
+#[test]
+fn snapshot_without_a_trailer_is_rejected() {
+    let lines = snapshot_lines(5);
+    let rejected = rejections(&lines[..lines.len() - 1]);
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].line, lines.len() as u64);
+}
+
+#[test]
+fn snapshot_with_a_missing_record_is_rejected() {
+    let mut lines = snapshot_lines(5);
+    lines.remove(2);
+    let rejected = rejections(&lines);
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].reason, "Snapshot trailer does not match its records.");
+}
+
+#[test]
+fn snapshot_with_a_corrupt_record_is_rejected() {
+    let mut lines = snapshot_lines(5);
+    lines[2] = lines[2].replace("definition ", "defamation ");
+    let rejected = rejections(&lines);
+    assert!(rejected.iter().any(|rejection| rejection.reason == "Record checksum mismatch."));
+}
+
+#[test]
+fn snapshot_that_fails_its_checks_restores_nothing() {
+    let lines = snapshot_lines(5);
+    for broken in &[lines[1..].to_vec(), lines[..lines.len() - 1].to_vec()] {
+        let (report, urban_ids) = restore_lines(broken);
+        assert_eq!(report.imported, 0);
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(urban_ids, vec![]);
+    }
+    let (report, urban_ids) = restore_lines(&lines);
+    assert_eq!(report.imported, 5);
+    assert_eq!(urban_ids, (0..5).collect::<Vec<_>>());
+}
+
+#[test]
+fn snapshot_restores_expiring_items_with_their_expiry() {
+    let source = ring(2);
+    store(&source[0].1, 5);
+    let expiring = definition(5);
+    let key = term_key(&expiring.canonical_term, &Normalization::default());
+    source[0]
+        .1
+        .set_expiring(key, expiring, Duration::from_secs(60))
+        .wait()
+        .unwrap();
+    let (snapshot, _) = write_snapshot(source[0].1.clone(), Vec::new(), 10)
+        .wait()
+        .unwrap();
+    let expires_at = String::from_utf8(snapshot.clone())
+        .unwrap()
+        .lines()
+        .filter_map(|line| match serde_json::from_str(line).unwrap() {
+                        SnapshotLine::Record { expires_at, .. } => expires_at,
+                        _ => None,
+                    })
+        .collect::<Vec<_>>();
+    assert_eq!(expires_at.len(), 1);
+
+    let target = ring(1);
+    let report = restore_snapshot(Cursor::new(snapshot), target[0].1.clone(), 10, 2)
+        .wait()
+        .unwrap();
+    assert_eq!(report.imported, 6);
+    let page = target[0]
+        .1
+        .scan(ScanBound::Included(MIN_KEY), MAX_KEY, 10)
+        .wait()
+        .unwrap();
+    assert_eq!(page.items.len(), 6);
+    assert_eq!(page.expiring.len(), 1);
+    assert_eq!(page.expiring[0].0, key);
+    // The TTL is worked out afresh when restoring, so the expiry may drift a little.
+    let drift = page.expiring[0].1 as i64 - expires_at[0] as i64;
+    assert!(drift.abs() < 1000, "expiry drifted by {}ms", drift);
+}
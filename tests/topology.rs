@@ -0,0 +1,45 @@
+extern crate tarpc;
+extern crate chord;
+
+mod common;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tarpc::futures::Future;
+use chord::*;
+use common::*;
+
+#[test]
+fn topology_lists_every_node_in_successor_order() {
+    let nodes = ring(4);
+    let topology = nodes[0].1.topology().wait().unwrap();
+    assert!(topology.unreachable.is_empty());
+    assert_eq!(topology.nodes.len(), 4);
+    assert_eq!(topology.nodes[0].id, nodes[0].0);
+    for pair in topology.nodes.windows(2) {
+        assert_eq!(pair[0].relations.unwrap().successor_id, pair[1].id);
+    }
+    assert_eq!(check_ring(&topology, &HashMap::new()), vec![]);
+}
+
+#[test]
+fn topology_goes_round_a_node_that_does_not_answer() {
+    let nodes = ring(4);
+    let unreachable = Id::from("127.0.0.1:1".parse::<SocketAddr>().unwrap());
+    assert!(nodes[0].1.succeed(unreachable).wait().unwrap());
+
+    let topology = nodes[0].1.topology().wait().unwrap();
+    assert_eq!(topology.unreachable, vec![unreachable]);
+    let mut found = topology
+        .nodes
+        .iter()
+        .map(|meta| meta.id)
+        .collect::<Vec<_>>();
+    let mut expected = nodes.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+    found.sort();
+    expected.sort();
+    assert_eq!(found, expected);
+
+    let violations = check_ring(&topology, &HashMap::new());
+    assert!(violations.contains(&Violation::Unreachable { node: unreachable }));
+}